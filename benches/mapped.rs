@@ -0,0 +1,50 @@
+use std::{env, fs, path::PathBuf, process};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use tgrep::utils::lines::LinesReader;
+use tgrep::utils::mapped::Mapped;
+
+const FILES: usize = 64;
+const FILE_SIZE: usize = 1 << 20;
+
+fn files() -> (PathBuf, Vec<PathBuf>) {
+    let dir = env::temp_dir().join(format!("tgrep-bench-mapped-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let line = "the quick brown fox jumps over the lazy dog\n";
+    let content = line.repeat(FILE_SIZE / line.len());
+    let files = (0..FILES)
+        .map(|i| {
+            let path = dir.join(format!("{}.txt", i));
+            fs::write(&path, &content).unwrap();
+            path
+        })
+        .collect();
+    (dir, files)
+}
+
+fn scan(files: &[PathBuf], populate: bool) -> usize {
+    let mut count = 0;
+    for path in files {
+        let len = fs::metadata(path).unwrap().len() as usize;
+        let mapped = Mapped::new(path, len, populate).unwrap();
+        let mut lines = mapped.lines().unwrap();
+        while lines.next().is_some() {
+            count += 1;
+        }
+    }
+    count
+}
+
+fn populate(c: &mut Criterion) {
+    let (dir, files) = files();
+    let mut group = c.benchmark_group("mmap");
+    group.sample_size(10);
+    group.bench_function("fault", |b| b.iter(|| scan(&files, false)));
+    group.bench_function("populate", |b| b.iter(|| scan(&files, true)));
+    group.finish();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+criterion_group!(benches, populate);
+criterion_main!(benches);
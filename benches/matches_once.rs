@@ -0,0 +1,75 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use regex::Regex;
+
+use tgrep::utils::display::{DisplayTerminal, Format};
+use tgrep::utils::grep;
+use tgrep::utils::lines::{LineIterator, LinesReader};
+use tgrep::utils::mapped::Mapped;
+use tgrep::utils::matcher::{Match, Matcher, MatcherOptions};
+use tgrep::utils::writer::BufferedWriter;
+
+const FILE_SIZE: usize = 16 << 20;
+
+/// Hides the mapping, so that the lines are scanned as before
+struct Lines(Mapped);
+
+impl LinesReader for Lines {
+    fn lines(&self) -> anyhow::Result<Box<LineIterator>> {
+        self.0.lines()
+    }
+
+    fn path(&self) -> &PathBuf {
+        self.0.path()
+    }
+}
+
+fn matcher() -> Matcher {
+    let regexp = Regex::new("needle").unwrap();
+    Arc::new(Box::new(move |line: &str, options| match options {
+        MatcherOptions::Fuzzy => regexp
+            .shortest_match(line)
+            .map(|pos| vec![Match::new(0, pos)]),
+        MatcherOptions::Exact(_) => regexp
+            .find(line)
+            .map(|m| vec![Match::new(m.start(), m.end())]),
+    }))
+}
+
+#[allow(clippy::arc_with_non_send_sync)]
+fn matches_once(c: &mut Criterion) {
+    let path = env::temp_dir().join(format!("tgrep-bench-matches-once-{}", process::id()));
+    let line = "the quick brown fox jumps over the lazy dog\n";
+    // The only match is on the last line
+    let content = line.repeat(FILE_SIZE / line.len()) + "needle\n";
+    fs::write(&path, &content).unwrap();
+    let display = Arc::new(DisplayTerminal::new(
+        usize::MAX,
+        Format::PathOnly { colour: false },
+        Arc::new(Box::new(|path: &Path| path.to_str().unwrap().to_owned())),
+        Arc::new(BufferedWriter::new()),
+    ));
+    let grep = grep::grep_matches_once();
+    let matcher = matcher();
+    let mapped = || Mapped::new(&path, content.len(), false).unwrap();
+
+    let mut group = c.benchmark_group("matches_once");
+    group.sample_size(10);
+    group.bench_function("mapped", |b| {
+        b.iter(|| grep(Arc::new(mapped()), matcher.clone(), display.clone()))
+    });
+    group.bench_function("lines", |b| {
+        b.iter(|| grep(Arc::new(Lines(mapped())), matcher.clone(), display.clone()))
+    });
+    group.finish();
+    fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(benches, matches_once);
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use regex::{Regex, RegexBuilder};
+
+const CONTENT_SIZE: usize = 16 << 20;
+
+fn count(regexp: &Regex, content: &str) -> usize {
+    content
+        .lines()
+        .map(|line| regexp.find_iter(line).count())
+        .sum()
+}
+
+fn unicode(c: &mut Criterion) {
+    let line = "the quick brown fox is jumping over the lazy dog, 42 times\n";
+    let content = line.repeat(CONTENT_SIZE / line.len());
+    let pattern = r"\b\w+ing\s\w+\b";
+    let build = |unicode| {
+        RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .unicode(unicode)
+            .build()
+            .unwrap()
+    };
+    let (unicode, ascii) = (build(true), build(false));
+
+    let mut group = c.benchmark_group("unicode");
+    group.sample_size(10);
+    group.bench_function("unicode", |b| b.iter(|| count(&unicode, &content)));
+    group.bench_function("ascii", |b| b.iter(|| count(&ascii, &content)));
+    group.finish();
+}
+
+criterion_group!(benches, unicode);
+criterion_main!(benches);
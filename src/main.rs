@@ -1,32 +1,76 @@
 use std::{
+    env,
+    ffi::OsString,
     fs,
+    num::NonZeroUsize,
     path::{self, Path, PathBuf},
+    str::FromStr,
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use anyhow::Error;
 use futures::executor::ThreadPool;
-use log::info;
-use regex::RegexBuilder;
-use structopt::StructOpt;
+use log::{debug, error, info, log_enabled, trace, Level};
+use regex::{Regex, RegexBuilder};
+use structopt::{clap::ArgGroup, StructOpt};
 
 mod utils;
 
-use crate::utils::display::{DisplayTerminal, Format, PathFormat};
+use crate::utils::decoded::Encoding;
+use crate::utils::display::{DisplayTerminal, Field, Format, HeadingPath, Offsets, PathFormat};
 use crate::utils::filters::Filters;
-use crate::utils::grep;
-use crate::utils::matcher::{Match, MatcherOptions};
+use crate::utils::gitdiff::{GitDiff, GitDiffMode, GitTracked};
+use crate::utils::grep::{self, CountUnit};
+use crate::utils::matcher::{skip_long_lines, Match, MatchLimit, Matcher, MatcherOptions, Unique};
+use crate::utils::paths;
 use crate::utils::patterns::Patterns;
+use crate::utils::preprocessor::Preprocessor;
+use crate::utils::replace::Replacer;
 use crate::utils::stdin::Stdin;
-use crate::utils::walker::{Walker, WalkerBuilder, GIT_DIR};
-use crate::utils::writer::StdoutWriter;
+use crate::utils::walker::{
+    Decision, EntryType, FileEvent, FilenameMatch, IgnoreCache, OnConsidered, OnFile, SortBy,
+    SortedOutputs, Stats, Walker, WalkerBuilder, GIT_DIR,
+};
+#[cfg(unix)]
+use crate::utils::writer::SocketWriter;
+use crate::utils::writer::{StdoutWriter, Writer};
 
 #[derive(Debug, StructOpt)]
+#[structopt(
+    group = ArgGroup::with_name("counts"),
+    after_help = "An `@file` argument is replaced by the lines of the file, one argument per line. `@@` stands for a literal `@`, e.g. `@@Override` searches for `@Override`, and the arguments after `--` are never replaced"
+)]
 struct Cli {
-    #[structopt(short = "i", help = "Case-insensitive search")]
+    #[structopt(
+        short = "i",
+        help = "Case-insensitive search, file filter patterns (-f, -t, --path-regex) included; inline flags in the pattern take precedence, e.g. `(?-i:Foo)bar`"
+    )]
     ignore_case: bool,
+    #[structopt(
+        short = "w",
+        long = "word-regexp",
+        help = "Match only whole words, word boundaries are Unicode-aware"
+    )]
+    word_regexp: bool,
+    #[structopt(
+        long = "ascii-word",
+        requires = "word-regexp",
+        help = "With -w, only ASCII letters, digits and `_` are word characters, e.g. `caf` matches in `café`"
+    )]
+    ascii_word: bool,
     #[structopt(long = "ignore-symlinks", help = "Do not follow symlinks")]
     ignore_symlinks: bool,
+    #[structopt(
+        long = "no-ignore",
+        help = "Do not apply .gitignore rules nor skip .git directories (hidden files are always searched)"
+    )]
+    no_ignore: bool,
+    #[structopt(
+        long = "no-negated-ignore",
+        help = "Ignore the `!` patterns of .gitignore files, so that re-included files stay ignored"
+    )]
+    no_negated_ignore: bool,
     #[structopt(short = "v", help = "Invert the sense of matching")]
     invert_match: bool,
     #[structopt(
@@ -41,25 +85,332 @@ struct Cli {
         help = "Show only files without match"
     )]
     files_without_match: bool,
+    #[structopt(
+        long = "files-zero-match",
+        help = "Show only files without a selected line: the same files as -L, but as the matches are counted rather than inverted, with -v the files whose every line matches"
+    )]
+    files_zero_match: bool,
     #[structopt(
         short = "o",
-        help = "Prints only the matching parts of the line (each matching part is printed on a separate output line)"
+        help = "Prints only the matching parts of the line (each matching part is printed on a separate output line), context lines of -A/-B are printed whole"
     )]
     match_only: bool,
+    #[structopt(
+        long = "capture",
+        requires = "match-only",
+        help = "With -o, print only the given capture group of each match, 0 being the whole match"
+    )]
+    capture: Option<usize>,
+    #[structopt(
+        long = "no-prefix-on-matches",
+        requires = "match-only",
+        help = "With -o, print the matches alone without the path and line number"
+    )]
+    no_prefix_on_matches: bool,
     #[structopt(
         short = "h",
         long = "no-path",
         help = "Suppress the prefixing of file names on output"
     )]
     no_path: bool,
+    #[structopt(
+        short = "H",
+        long = "with-path",
+        conflicts_with = "no-path",
+        help = "Prefix the output with the path even when a single file or stdin is the only input"
+    )]
+    with_path: bool,
+    #[structopt(
+        long = "replace",
+        name = "replace",
+        help = "Print matching lines with the matches replaced, `$1`/`${name}` refer to capture groups"
+    )]
+    replace: Option<String>,
+    #[structopt(
+        long = "write",
+        requires = "replace",
+        help = "DESTRUCTIVE: rewrite the files in place with the --replace substitution applied, like `sed -i`, and print the modified paths"
+    )]
+    write: bool,
+    #[structopt(
+        short = "U",
+        long = "multiline",
+        help = "Allow matches to span multiple lines"
+    )]
+    multiline: bool,
+    #[structopt(
+        long = "passthrough",
+        help = "Print all lines of the matching files, highlighting the matches"
+    )]
+    passthrough: bool,
     #[structopt(long = "no-lno", help = "Do not print line numbers")]
     no_lno: bool,
+    #[structopt(
+        long = "line-number-width",
+        default_value = "0",
+        help = "Right-align line numbers to the given width"
+    )]
+    line_number_width: usize,
+    #[structopt(
+        short = "p",
+        long = "pretty",
+        help = "Interactive output: colours, line numbers and --filename-once; colours and line numbers are on by default, --no-colour and --no-lno still turn them off"
+    )]
+    pretty: bool,
+    #[structopt(
+        long = "filename-once",
+        help = "Name the file on its first matching line only, the following lines of the file have just the line number"
+    )]
+    filename_once: bool,
+    #[structopt(
+        long = "heading-path",
+        help = "How --filename-once names the file: `full` (absolute), `relative` (as the other paths) or `basename`"
+    )]
+    heading_path: Option<HeadingPath>,
+    #[structopt(
+        long = "zero-based-lines",
+        help = "Number lines from 0 instead of 1, context lines included"
+    )]
+    zero_based_lines: bool,
+    #[structopt(
+        long = "field",
+        require_delimiter = true,
+        help = "Print only the given comma separated fields of matching lines, joined by `:`: `path`, `lno`, `col` (1-based byte column of the first match), `line` and `match` (the text of every match on its own line, `col` is then its column)"
+    )]
+    fields: Vec<Field>,
+    #[structopt(
+        long = "show-whitespace",
+        help = "Show tabs as `→` and trailing spaces as `·`, the markers are dimmed when colours are enabled"
+    )]
+    show_whitespace: bool,
+    #[structopt(
+        long = "offsets",
+        default_value = "original",
+        help = "Whether `--field col` is the column in the `original` line or in the `displayed` one, they differ with --show-whitespace"
+    )]
+    offsets: Offsets,
+    #[structopt(
+        long = "wrap",
+        help = "Wrap long lines at the terminal width instead of trimming them around the match"
+    )]
+    wrap: bool,
     #[structopt(
         short = "c",
         long = "count",
-        help = "Count the number of the occurences"
+        alias = "count-lines",
+        group = "counts",
+        help = "Count the number of matching lines per file (with -o, the number of matches per line), `foo foo` counts as 1"
     )]
     count: bool,
+    #[structopt(
+        long = "count-percent",
+        group = "counts",
+        help = "Print the percentage of matching lines per file instead of their number, rounded down"
+    )]
+    count_percent: bool,
+    #[structopt(
+        long = "count-matches",
+        group = "counts",
+        help = "Count the number of matches per file, `foo foo` counts as 2"
+    )]
+    count_matches: bool,
+    #[structopt(
+        long = "include-zero",
+        requires = "counts",
+        help = "With -c or --count-matches, also report the files without matches"
+    )]
+    include_zero: bool,
+    #[structopt(
+        short = "z",
+        long = "search-zip",
+        help = "Search in gzip-compressed files"
+    )]
+    search_zip: bool,
+    /// Searches the text members of `.tar`, `.tar.gz` and `.tgz` archives as if they were files,
+    /// named `archive.tar:member` in the output. Members are read into memory one at a time,
+    /// so a large member takes as much memory as its size.
+    #[cfg(feature = "archive")]
+    #[structopt(long = "search-archive")]
+    search_archive: bool,
+    #[structopt(
+        long = "pre",
+        help = "Search the output of `COMMAND <path>` instead of the file contents"
+    )]
+    pre: Option<String>,
+    #[structopt(
+        long = "pre-glob",
+        number_of_values = 1,
+        help = "Only run files matching the pattern through the --pre command"
+    )]
+    pre_globs: Vec<String>,
+    #[structopt(
+        long = "no-mmap-glob",
+        number_of_values = 1,
+        help = "Read files matching the pattern with buffered reads instead of mapping them into memory"
+    )]
+    no_mmap_globs: Vec<String>,
+    #[structopt(
+        long = "mmap-populate",
+        help = "Read the whole file ahead when mapping it into memory, faster on slow disks but wasteful for files that match early, e.g. with -l"
+    )]
+    mmap_populate: bool,
+    #[structopt(
+        long = "max-matches-total",
+        help = "Stop searching after the given number of matches across all files, a line selected by -v counting as one (which matches are reported is only deterministic with --threads 1)"
+    )]
+    max_matches_total: Option<usize>,
+    #[structopt(long = "threads", help = "Number of threads used to search files")]
+    threads: Option<NonZeroUsize>,
+    #[structopt(
+        long = "line-buffered",
+        alias = "unbuffered",
+        help = "Print the lines as they are found instead of once a file is searched; the lines of different files interleave unless used with --threads 1"
+    )]
+    line_buffered: bool,
+    #[structopt(
+        long = "relative",
+        help = "Print paths relative to the current directory regardless of how they were given"
+    )]
+    relative: bool,
+    #[structopt(
+        long = "absolute",
+        conflicts_with = "relative",
+        help = "Print absolute canonicalized paths"
+    )]
+    absolute: bool,
+    #[structopt(
+        long = "repo-relative",
+        conflicts_with_all = &["relative", "absolute"],
+        help = "Print paths relative to the root of the git repository containing the searched path, or as given outside of a repository"
+    )]
+    repo_relative: bool,
+    #[structopt(
+        long = "path-separator",
+        help = "Print paths with the separator instead of the native one, e.g. `/` on Windows"
+    )]
+    path_separator: Option<char>,
+    #[structopt(
+        long = "filename-match",
+        help = "Match the pattern against file paths instead of the file contents"
+    )]
+    filename_match: bool,
+    #[structopt(
+        long = "filename-match-type",
+        help = "Entries reported by --filename-match and --filename-only: `file` (default), `dir`, `symlink` (tested by the link name) or `any`"
+    )]
+    filename_match_type: Option<EntryType>,
+    #[structopt(
+        long = "filename-only",
+        help = "Match the pattern against file names instead of the file contents"
+    )]
+    filename_only: bool,
+    #[structopt(
+        long = "sort",
+        possible_values = &["count", "modified"],
+        help = "Sort the output of the files, forcing the whole output to be buffered: `count` sorts by the number of matching lines in descending order, `modified` by the modification time in ascending order"
+    )]
+    sort_by: Option<SortBy>,
+    #[structopt(
+        long = "sortr",
+        possible_values = &["count", "modified"],
+        conflicts_with = "sort",
+        help = "Same as --sort but in reverse order"
+    )]
+    sort_by_reverse: Option<SortBy>,
+    #[structopt(
+        long = "stats",
+        help = "Print the number of searched and skipped files, the scanned bytes and the elapsed time to stderr"
+    )]
+    stats: bool,
+    #[structopt(
+        long = "changed-within",
+        parse(try_from_str = parse_duration),
+        help = "Only search files modified within the duration, e.g. `30m`, `24h` or `7d` (units: s, m, h, d, w); explicitly given files are always searched"
+    )]
+    changed_within: Option<Duration>,
+    #[structopt(
+        long = "changed-before",
+        parse(try_from_str = parse_duration),
+        help = "Only search files modified earlier than the duration ago, the complement of --changed-within"
+    )]
+    changed_before: Option<Duration>,
+    #[structopt(
+        long = "summary-only",
+        help = "Print nothing but the number of matching files, searched files and matching lines at the end"
+    )]
+    summary_only: bool,
+    #[structopt(
+        long = "word-count",
+        help = "Print the number of words of every file instead of searching it, then the total; all the positional arguments are paths"
+    )]
+    word_count: bool,
+    #[structopt(
+        long = "char-count",
+        help = "Print the number of characters, line terminators excluded, of every file instead of searching it, then the total; all the positional arguments are paths"
+    )]
+    char_count: bool,
+    /// Match ASCII only with `\w`, `\d`, `\s`, `\b` and case insensitivity, which is faster.
+    ///
+    /// Patterns with Unicode classes such as `\p{Greek}` are rejected, and so are the ones that
+    /// could match a part of a multi-byte character, e.g. `.` or `[^a]`.
+    #[structopt(long = "no-unicode")]
+    no_unicode: bool,
+    #[structopt(
+        long = "timeout-per-file",
+        help = "Stop searching a file after the given number of milliseconds with a warning, the lines matched by then are printed; checked every 1024 lines, so a single slow line or the whole file prefilter may overrun it"
+    )]
+    timeout_per_file: Option<u64>,
+    #[structopt(
+        long = "max-line-length",
+        help = "Do not match lines longer than the given number of bytes, e.g. minified files"
+    )]
+    max_line_length: Option<usize>,
+    #[structopt(
+        long = "unique",
+        help = "Print each distinct matching line once; as files are searched concurrently, which file reports a line shared by several ones is not defined"
+    )]
+    unique: bool,
+    #[structopt(
+        long = "section-regex",
+        name = "section-regex",
+        help = "Only report matches within the sections whose header line matches the pattern; lines before the first header belong to no section"
+    )]
+    section_regex: Option<String>,
+    #[structopt(
+        long = "section-header",
+        requires = "section-regex",
+        help = "Pattern of the lines starting a section, INI-like `[name]` headers by default"
+    )]
+    section_header: Option<String>,
+    #[structopt(
+        long = "git-diff",
+        possible_values = &["tracked", "all"],
+        require_equals = true,
+        help = "Only match the lines added or changed in the working tree relative to HEAD; untracked files are ignored unless `--git-diff=all`"
+    )]
+    git_diff: Option<Option<GitDiffMode>>,
+    #[structopt(
+        long = "git-tracked",
+        help = "Only search the files tracked by git, found with `git ls-files`; files outside of a repository are skipped unless given on the command line"
+    )]
+    git_tracked: bool,
+    #[structopt(
+        long = "binary",
+        help = "Search binary files, reporting matches as `Binary file <path> matches`"
+    )]
+    binary: bool,
+    #[structopt(
+        long = "skip-binary-ext",
+        help = "Skip files with a common binary extension, e.g. `.png` or `.so`, without reading them"
+    )]
+    skip_binary_ext: bool,
+    #[structopt(
+        long = "binary-ext",
+        number_of_values = 1,
+        requires = "skip-binary-ext",
+        help = "Extension to skip with --skip-binary-ext in addition to the built-in ones"
+    )]
+    binary_exts: Vec<String>,
     #[structopt(long = "no-colour", help = "Disable colours")]
     no_colour: bool,
     #[structopt(long = "no-color", help = "Disable colours")]
@@ -70,6 +421,16 @@ struct Cli {
         help = "Number of lines to print after each match"
     )]
     after: Option<usize>,
+    #[structopt(
+        long = "context-paragraph",
+        help = "Print the lines after each match up to the next blank line, `-A` caps the number of lines"
+    )]
+    context_paragraph: bool,
+    #[structopt(
+        long = "separator-after-last",
+        help = "With context, also print the `--` file separator after the last file"
+    )]
+    separator_after_last: bool,
     #[structopt(
         short = "B",
         long = "before-context",
@@ -83,9 +444,30 @@ struct Cli {
         help = "Exclude pattern"
     )]
     force_ignore_patterns: Vec<String>,
+    #[structopt(
+        long = "exclude-from",
+        number_of_values = 1,
+        parse(from_os_str),
+        help = "Read exclude patterns from the file, one per line as in a `.gitignore`"
+    )]
+    exclude_from: Vec<PathBuf>,
+    #[structopt(
+        long = "ignore-file-root",
+        parse(from_os_str),
+        requires = "exclude-from",
+        help = "Anchor the --exclude-from patterns at the directory instead of each searched path"
+    )]
+    ignore_file_root: Option<PathBuf>,
+    #[structopt(
+        long = "ignore-scan-root",
+        number_of_values = 1,
+        parse(from_os_str),
+        help = "Stop looking for the `.gitignore` files of the parents at the directory rather than at the first parent containing `.git`, may be repeated"
+    )]
+    ignore_scan_roots: Vec<PathBuf>,
     #[structopt(
         short = "f",
-        help = "File filter pattern",
+        help = "File filter pattern, anchored at the search root if it contains a slash",
         number_of_values = 1,
         name = "filter-pattern"
     )]
@@ -96,8 +478,66 @@ struct Cli {
         number_of_values = 1
     )]
     file_type_filters: Vec<String>,
-    regexp: String,
-    #[structopt(parse(from_os_str))]
+    #[structopt(
+        long = "path-regex",
+        help = "Only search files whose path relative to the searched directory matches the regular expression, on top of the file filters"
+    )]
+    path_regex: Option<String>,
+    #[structopt(
+        long = "glob-case-insensitive",
+        help = "Match file filter patterns (-f, -t, --path-regex) case-insensitively, implied by -i"
+    )]
+    glob_case_insensitive: bool,
+    #[structopt(
+        long = "engine",
+        default_value = "regex",
+        possible_values = &["regex", "literal", "auto"],
+        help = "How to interpret the pattern: `literal` searches for it verbatim, `auto` does so unless it contains any of `\\ . + * ? ( ) | [ ] { } ^ $`"
+    )]
+    engine: Engine,
+    #[structopt(
+        long = "glob-pattern",
+        help = "The pattern is a shell glob matched against whole lines, e.g. `*TODO*` for lines containing `TODO`"
+    )]
+    glob_pattern: bool,
+    #[structopt(
+        long = "encoding",
+        help = "Decode files from the given encoding; `auto` follows editor coding declarations (`-*- coding: latin-1 -*-`, `fileencoding=latin1`) in the first two lines and falls back to UTF-8"
+    )]
+    encoding: Option<Encoding>,
+    #[structopt(
+        long = "encoding-fallback",
+        conflicts_with = "encoding",
+        parse(try_from_str = parse_encoding_label),
+        help = "Decode the lines that are not valid UTF-8 from the given encoding, e.g. `latin1` in repositories mixing encodings; files with such lines are not searched as a whole first"
+    )]
+    encoding_fallback: Option<&'static encoding_rs::Encoding>,
+    #[structopt(
+        long = "files",
+        help = "List the files that would be searched, all positional arguments are paths"
+    )]
+    files: bool,
+    #[structopt(
+        long = "json",
+        help = "Print JSON objects, one per line: matching lines as with --socket, enclosed by `begin` and `end` events per file with a `type` field, the latter with the `matches` and searched `bytes` counts; with --files the files with `path`, `size`, `type` (empty, text, binary, gzip or preprocessed) and `mmap` fields"
+    )]
+    json: bool,
+    /// Experimental: send the results to a Unix domain socket instead of stdout
+    ///
+    /// tgrep connects to the socket, which the receiving end (e.g. an editor plugin) must be
+    /// listening on, and writes newline-delimited JSON objects: one per matching line with `path`,
+    /// `lno`, `line` and `matches` (byte ranges of the matches within the line) fields, or one
+    /// with just `path` when only paths are printed. The records of every file are enclosed by
+    /// `begin` and `end` events as with --json.
+    #[cfg(unix)]
+    #[structopt(long = "socket", parse(from_os_str))]
+    socket: Option<PathBuf>,
+    #[structopt(required_unless_one = &["files", "word_count", "char_count"])]
+    regexp: Option<String>,
+    #[structopt(
+        parse(from_os_str),
+        help = "Paths to search; when stdin is piped it is searched after all paths, even if some of them fail"
+    )]
     paths: Vec<PathBuf>,
     #[structopt(long = "path", name = "path", number_of_values = 1, parse(from_os_str))]
     opt_paths: Vec<PathBuf>,
@@ -109,6 +549,176 @@ struct Cli {
     verbosity: i8,
 }
 
+#[derive(Clone, Copy, Debug)]
+enum Engine {
+    Regex,
+    Literal,
+    Auto,
+}
+
+impl FromStr for Engine {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "regex" => Ok(Engine::Regex),
+            "literal" => Ok(Engine::Literal),
+            "auto" => Ok(Engine::Auto),
+            _ => anyhow::bail!("unknown engine '{}'", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Word {
+    Unicode,
+    Ascii,
+}
+
+/// The compiled search pattern
+enum SearchPattern {
+    /// Matches lines, the second regex runs the pre-check over the whole content
+    Regex(Regex, Regex),
+    Glob(glob::Pattern, glob::MatchOptions),
+}
+
+/// INI-like `[name]` header, surrounding whitespace is allowed
+const SECTION_HEADER: &str = r"^\s*\[.*\]\s*$";
+
+const REGEX_METACHARACTERS: &[char] = &[
+    '\\', '.', '+', '*', '?', '(', ')', '|', '[', ']', '{', '}', '^', '$',
+];
+
+/// How deep response files may refer to other ones, guards against cycles
+const RESPONSE_FILE_DEPTH: usize = 8;
+
+/// Replaces `@file` arguments with the lines of the file, recursively.
+/// As with compilers, an argument naming no readable file is kept as is.
+/// `@@` stands for a literal `@` and the arguments after `--`, which may come
+/// from a response file, are kept as is.
+fn expand_response_files(
+    args: impl IntoIterator<Item = OsString>,
+    depth: usize,
+    options_ended: &mut bool,
+) -> Result<Vec<OsString>, Error> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        if *options_ended {
+            expanded.push(arg);
+            continue;
+        }
+        if arg == "--" {
+            *options_ended = true;
+            expanded.push(arg);
+            continue;
+        }
+        let path = match arg.to_str().and_then(|arg| arg.strip_prefix('@')) {
+            Some(escaped) if escaped.starts_with('@') => {
+                expanded.push(OsString::from(escaped));
+                continue;
+            }
+            Some(path) => path,
+            None => {
+                expanded.push(arg);
+                continue;
+            }
+        };
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => {
+                expanded.push(arg);
+                continue;
+            }
+        };
+        if depth == RESPONSE_FILE_DEPTH {
+            anyhow::bail!("response files nested too deeply at '{}'", path);
+        }
+        let lines: Vec<_> = content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(OsString::from)
+            .collect();
+        expanded.extend(expand_response_files(lines, depth + 1, options_ended)?);
+    }
+    Ok(expanded)
+}
+
+/// Turns the pattern into a regular expression according to the engine
+fn pattern(regexp: &str, engine: Engine, word: Option<Word>) -> String {
+    let literal = match engine {
+        Engine::Regex => false,
+        Engine::Literal => true,
+        Engine::Auto => !regexp.contains(REGEX_METACHARACTERS),
+    };
+    let regexp = if literal {
+        regex::escape(regexp)
+    } else {
+        regexp.to_owned()
+    };
+    match word {
+        Some(Word::Unicode) => format!(r"\b(?:{})\b", regexp),
+        Some(Word::Ascii) => format!(r"(?-u:\b)(?:{})(?-u:\b)", regexp),
+        None => regexp,
+    }
+}
+
+#[cfg(unix)]
+fn socket(args: &Cli) -> Option<&Path> {
+    args.socket.as_deref()
+}
+
+#[cfg(not(unix))]
+fn socket(_args: &Cli) -> Option<&Path> {
+    None
+}
+
+fn count_unit(args: &Cli) -> Option<CountUnit> {
+    if args.word_count {
+        Some(CountUnit::Words)
+    } else if args.char_count {
+        Some(CountUnit::Chars)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "archive")]
+fn search_archive(args: &Cli) -> bool {
+    args.search_archive
+}
+
+#[cfg(not(feature = "archive"))]
+fn search_archive(_args: &Cli) -> bool {
+    false
+}
+
+fn parse_encoding_label(s: &str) -> Result<&'static encoding_rs::Encoding, Error> {
+    match s.parse()? {
+        Encoding::Label(encoding) => Ok(encoding),
+        Encoding::Auto => anyhow::bail!("a fallback encoding can't be detected"),
+    }
+}
+
+/// Parses a number followed by one of the `s`, `m`, `h`, `d` or `w` units, e.g. `36h`
+fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => anyhow::bail!("duration '{}' lacks a unit (s, m, h, d or w)", s),
+    };
+    match s[..s.len() - 1]
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(unit))
+    {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => anyhow::bail!("invalid duration '{}'", s),
+    }
+}
+
 fn log_level(verbosity: i8) -> log::LevelFilter {
     match verbosity {
         std::i8::MIN..=-1 => log::LevelFilter::Off,
@@ -120,23 +730,368 @@ fn log_level(verbosity: i8) -> log::LevelFilter {
     }
 }
 
+/// Rejects nonsensical combinations of flags instead of silently ignoring some of them:
+///
+/// |          | -v | -l | -L | -o | -c | -A/-B |
+/// |----------|----|----|----|----|----|-------|
+/// | -l       | ok |    | no | no | no | no    |
+/// | -L       | no | no |    | no | no | no    |
+/// | -c       | ok | no | no | ok |    | no    |
+///
+/// `--passthrough` is only compatible with `-v`, `-U` and `--replace` only with `-o`.
+/// `--section-regex` and `--git-diff` report matching lines and are only compatible with `-v` and `-o`.
+/// `--write` only rewrites plain UTF-8 text files.
+/// `--filename-match` and `--filename-only` report paths and are incompatible with `-c`,
+/// `--count-matches`, `-A/-B` and `--replace`.
+///
+/// `-o -c` reports the number of matches per line, `-v -c` the number of non-matching lines.
+/// With `--json` counts are reported as numbers, neither per line nor as percentages,
+/// and line numbers are always 1-based. `--field match` has nothing to print for `-v` lines.
+fn check_conflicts(args: &Cli) -> Result<(), Error> {
+    let context = args.before.is_some() || args.after.is_some() || args.context_paragraph;
+    let replace = args.replace.is_some();
+    let section = args.section_regex.is_some();
+    let git_diff = args.git_diff.is_some();
+    let sort = args.sort_by.is_some() || args.sort_by_reverse.is_some();
+    let counting = count_unit(args).is_some();
+    let fields = !args.fields.is_empty();
+    let json = args.json || socket(args).is_some();
+    let filename = args.filename_match || args.filename_only;
+    let conflicts = [
+        (args.files_without_match && args.invert_match, "-L and -v"),
+        (
+            args.files_without_match && args.files_with_match,
+            "-L and -l",
+        ),
+        (args.files_without_match && args.match_only, "-L and -o"),
+        (args.files_without_match && args.count, "-L and -c"),
+        (args.files_without_match && context, "-L and -A/-B"),
+        (args.files_with_match && args.match_only, "-l and -o"),
+        (args.files_with_match && args.count, "-l and -c"),
+        (args.files_with_match && context, "-l and -A/-B"),
+        (
+            args.files_zero_match && args.files_with_match,
+            "--files-zero-match and -l",
+        ),
+        (
+            args.files_zero_match && args.files_without_match,
+            "--files-zero-match and -L",
+        ),
+        (
+            args.files_zero_match && args.match_only,
+            "--files-zero-match and -o",
+        ),
+        (
+            args.files_zero_match && args.count,
+            "--files-zero-match and -c",
+        ),
+        (
+            args.files_zero_match && context,
+            "--files-zero-match and -A/-B",
+        ),
+        (
+            args.files_zero_match && args.passthrough,
+            "--files-zero-match and --passthrough",
+        ),
+        (
+            args.files_zero_match && args.multiline,
+            "--files-zero-match and -U",
+        ),
+        (
+            args.files_zero_match && replace,
+            "--files-zero-match and --replace",
+        ),
+        (
+            args.files_zero_match && args.files,
+            "--files-zero-match and --files",
+        ),
+        (
+            args.files_zero_match && section,
+            "--files-zero-match and --section-regex",
+        ),
+        (
+            args.files_zero_match && git_diff,
+            "--files-zero-match and --git-diff",
+        ),
+        (
+            args.files_zero_match && args.summary_only,
+            "--files-zero-match and --summary-only",
+        ),
+        (
+            args.files_zero_match && counting,
+            "--files-zero-match and --word-count/--char-count",
+        ),
+        (
+            args.count && args.match_only && args.invert_match,
+            "-c -o and -v",
+        ),
+        (args.count && context, "-c and -A/-B"),
+        // The counts would stop at the limit
+        (
+            args.count && args.max_matches_total.is_some(),
+            "-c and --max-matches-total",
+        ),
+        (
+            args.count_matches && args.match_only,
+            "--count-matches and -o",
+        ),
+        (
+            args.count_matches && args.invert_match,
+            "--count-matches and -v",
+        ),
+        (
+            args.count_percent && args.match_only,
+            "--count-percent and -o",
+        ),
+        (
+            args.passthrough && args.files_with_match,
+            "--passthrough and -l",
+        ),
+        (
+            args.passthrough && args.files_without_match,
+            "--passthrough and -L",
+        ),
+        (args.passthrough && args.match_only, "--passthrough and -o"),
+        (args.wrap && args.match_only, "--wrap and -o"),
+        (args.passthrough && args.count, "--passthrough and -c"),
+        (args.passthrough && context, "--passthrough and -A/-B"),
+        (args.multiline && args.invert_match, "-U and -v"),
+        (args.multiline && args.files_with_match, "-U and -l"),
+        (args.multiline && args.files_without_match, "-U and -L"),
+        (args.multiline && args.count, "-U and -c"),
+        (args.multiline && context, "-U and -A/-B"),
+        (args.multiline && args.passthrough, "-U and --passthrough"),
+        (replace && args.invert_match, "--replace and -v"),
+        (replace && args.files_with_match, "--replace and -l"),
+        (replace && args.files_without_match, "--replace and -L"),
+        (replace && args.count, "--replace and -c"),
+        (replace && context, "--replace and -A/-B"),
+        (replace && args.multiline, "--replace and -U"),
+        (replace && args.passthrough, "--replace and --passthrough"),
+        (args.write && args.match_only, "--write and -o"),
+        (args.write && args.binary, "--write and --binary"),
+        (
+            args.binary && args.skip_binary_ext,
+            "--binary and --skip-binary-ext",
+        ),
+        (args.write && args.search_zip, "--write and -z"),
+        (
+            args.write && search_archive(args),
+            "--write and --search-archive",
+        ),
+        (args.write && args.pre.is_some(), "--write and --pre"),
+        (
+            args.write && args.encoding.is_some(),
+            "--write and --encoding",
+        ),
+        (args.files && args.count, "--files and -c"),
+        (args.files && args.files_with_match, "--files and -l"),
+        (args.files && args.files_without_match, "--files and -L"),
+        (args.files && replace, "--files and --replace"),
+        (socket(args).is_some() && context, "--socket and -A/-B"),
+        (args.unique && args.multiline, "--unique and -U"),
+        (
+            args.max_line_length.is_some() && args.multiline,
+            "--max-line-length and -U",
+        ),
+        (section && args.files_with_match, "--section-regex and -l"),
+        (
+            section && args.files_without_match,
+            "--section-regex and -L",
+        ),
+        (section && args.count, "--section-regex and -c"),
+        (section && context, "--section-regex and -A/-B"),
+        (section && args.multiline, "--section-regex and -U"),
+        (
+            section && args.passthrough,
+            "--section-regex and --passthrough",
+        ),
+        (section && replace, "--section-regex and --replace"),
+        (section && args.files, "--section-regex and --files"),
+        (git_diff && args.files_with_match, "--git-diff and -l"),
+        (git_diff && args.files_without_match, "--git-diff and -L"),
+        (git_diff && args.count, "--git-diff and -c"),
+        (git_diff && context, "--git-diff and -A/-B"),
+        (git_diff && args.multiline, "--git-diff and -U"),
+        (git_diff && args.passthrough, "--git-diff and --passthrough"),
+        (git_diff && replace, "--git-diff and --replace"),
+        (git_diff && section, "--git-diff and --section-regex"),
+        (git_diff && args.files, "--git-diff and --files"),
+        (args.line_buffered && sort, "--line-buffered and --sort"),
+        (args.line_buffered && context, "--line-buffered and -A/-B"),
+        (
+            args.summary_only && args.files_with_match,
+            "--summary-only and -l",
+        ),
+        (
+            args.summary_only && args.files_without_match,
+            "--summary-only and -L",
+        ),
+        (args.summary_only && args.count, "--summary-only and -c"),
+        (args.summary_only && context, "--summary-only and -A/-B"),
+        (args.summary_only && args.multiline, "--summary-only and -U"),
+        (
+            args.summary_only && args.passthrough,
+            "--summary-only and --passthrough",
+        ),
+        (args.summary_only && replace, "--summary-only and --replace"),
+        (
+            args.summary_only && args.files,
+            "--summary-only and --files",
+        ),
+        (
+            args.glob_pattern && args.word_regexp,
+            "--glob-pattern and -w",
+        ),
+        (
+            args.glob_pattern && args.capture.is_some(),
+            "--glob-pattern and --capture",
+        ),
+        (args.glob_pattern && replace, "--glob-pattern and --replace"),
+        (args.glob_pattern && args.multiline, "--glob-pattern and -U"),
+        (
+            args.glob_pattern && args.no_unicode,
+            "--glob-pattern and --no-unicode",
+        ),
+        (
+            args.word_count && args.char_count,
+            "--word-count and --char-count",
+        ),
+        (counting && args.count, "--word-count/--char-count and -c"),
+        (
+            counting && args.files_with_match,
+            "--word-count/--char-count and -l",
+        ),
+        (
+            counting && args.files_without_match,
+            "--word-count/--char-count and -L",
+        ),
+        (
+            counting && args.files,
+            "--word-count/--char-count and --files",
+        ),
+        (counting && context, "--word-count/--char-count and -A/-B"),
+        (
+            counting && args.multiline,
+            "--word-count/--char-count and -U",
+        ),
+        (
+            counting && args.passthrough,
+            "--word-count/--char-count and --passthrough",
+        ),
+        (
+            counting && replace,
+            "--word-count/--char-count and --replace",
+        ),
+        (
+            counting && args.summary_only,
+            "--word-count/--char-count and --summary-only",
+        ),
+        (
+            counting && section,
+            "--word-count/--char-count and --section-regex",
+        ),
+        (
+            counting && git_diff,
+            "--word-count/--char-count and --git-diff",
+        ),
+        (counting && json, "--word-count/--char-count and --json"),
+        (args.count && args.match_only && json, "-c -o and --json"),
+        (args.count_percent && json, "--count-percent and --json"),
+        (fields && json, "--field and --json"),
+        (
+            args.fields.contains(&Field::Match) && args.invert_match,
+            "--field match and -v",
+        ),
+        (
+            json && args.zero_based_lines,
+            "--json and --zero-based-lines",
+        ),
+        (fields && args.count, "--field and -c"),
+        (fields && context, "--field and -A/-B"),
+        (fields && args.passthrough, "--field and --passthrough"),
+        (fields && counting, "--field and --word-count/--char-count"),
+        (
+            filename && args.count_matches,
+            "--filename-match/--filename-only and --count-matches",
+        ),
+        (
+            filename && args.count,
+            "--filename-match/--filename-only and -c",
+        ),
+        (
+            filename && context,
+            "--filename-match/--filename-only and -A/-B",
+        ),
+        (
+            filename && replace,
+            "--filename-match/--filename-only and --replace",
+        ),
+    ];
+    for (conflict, flags) in conflicts {
+        if conflict {
+            anyhow::bail!("incompatible flags: {}", flags);
+        }
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Error> {
-    let args = Cli::from_args();
+    let mut args = env::args_os();
+    // The program name is never a response file
+    let args = args
+        .next()
+        .into_iter()
+        .chain(expand_response_files(args, 0, &mut false)?);
+    let mut args = Cli::from_iter(args);
+    if args.pretty {
+        args.filename_once = true;
+    }
+    // The other counts conflict with the same flags
+    if args.count_matches || args.count_percent {
+        args.count = true;
+    }
 
     env_logger::Builder::new()
         .filter_level(log_level(args.verbosity))
         .parse_default_env()
         .init();
+    check_conflicts(&args)?;
+    if args.filename_match_type.is_some() && !args.filename_match && !args.filename_only {
+        anyhow::bail!("--filename-match-type requires --filename-match or --filename-only");
+    }
+    if args.heading_path.is_some() && !args.filename_once {
+        anyhow::bail!("--heading-path requires --filename-once or -p");
+    }
+    let count_unit = count_unit(&args);
+    let (writer, json): (Arc<dyn Writer>, bool) = match socket(&args) {
+        Some(path) => (Arc::new(SocketWriter::connect(path)?), true),
+        None => (Arc::new(StdoutWriter::new()), args.json),
+    };
 
     let stdin = Stdin::new();
-    let paths = if args.paths.is_empty() && args.opt_paths.is_empty() {
-        if stdin.is_readable() {
+    if args.write && stdin.is_readable() {
+        anyhow::bail!("--write can't modify stdin");
+    }
+    if args.git_diff.is_some() && stdin.is_readable() {
+        anyhow::bail!("--git-diff can't restrict stdin");
+    }
+    // stdin is never listed
+    let search_stdin = stdin.is_readable() && !args.files;
+    let (regexp, paths) = if args.files || count_unit.is_some() {
+        let paths = args.regexp.iter().map(PathBuf::from).chain(args.paths);
+        (String::new(), paths.collect())
+    } else {
+        (args.regexp.unwrap(), args.paths)
+    };
+    let paths = if paths.is_empty() && args.opt_paths.is_empty() {
+        if search_stdin {
             vec![]
         } else {
             vec![PathBuf::from(".")]
         }
     } else {
-        args.paths
+        paths
     };
     let paths = {
         let mut paths = paths.clone();
@@ -145,20 +1100,65 @@ fn main() -> Result<(), Error> {
     };
     info!(
         "regexp={:?}, paths={:?}, stdin={:?}",
-        args.regexp,
-        paths,
-        stdin.is_readable()
+        regexp, paths, search_stdin
     );
 
-    let regexp = RegexBuilder::new(args.regexp.as_str())
-        .case_insensitive(args.ignore_case)
-        .build()?;
-    let width = if let Some((width, _)) = term_size::dimensions() {
-        width
+    let word = match (args.word_regexp, args.ascii_word) {
+        (true, true) => Some(Word::Ascii),
+        (true, false) => Some(Word::Unicode),
+        _ => None,
+    };
+    let search_pattern = if args.glob_pattern {
+        let options = glob::MatchOptions {
+            case_sensitive: !args.ignore_case,
+            ..Default::default()
+        };
+        SearchPattern::Glob(glob::Pattern::new(&regexp)?, options)
     } else {
-        usize::MAX
+        let pattern = pattern(&regexp, args.engine, word);
+        let regexp = RegexBuilder::new(&pattern)
+            .case_insensitive(args.ignore_case)
+            .unicode(!args.no_unicode)
+            .build()?;
+        // The pre-check runs over the whole file while lines are matched one by one,
+        // `^` and `$` must match at line boundaries there
+        let fuzzy_regexp = RegexBuilder::new(&pattern)
+            .case_insensitive(args.ignore_case)
+            .unicode(!args.no_unicode)
+            .multi_line(true)
+            .crlf(true)
+            .build()?;
+        SearchPattern::Regex(regexp, fuzzy_regexp)
+    };
+    let capture = args.capture.unwrap_or(0);
+    if let SearchPattern::Regex(regexp, _) = &search_pattern {
+        if capture >= regexp.captures_len() {
+            anyhow::bail!("the pattern has no capture group {}", capture);
+        }
+    }
+    let section = match &args.section_regex {
+        Some(section) => {
+            let header = args.section_header.as_deref().unwrap_or(SECTION_HEADER);
+            Some((Regex::new(header)?, Regex::new(section)?))
+        }
+        None => None,
+    };
+    // --replace conflicts with --glob-pattern
+    let replacer = match (&args.replace, &search_pattern) {
+        (Some(replacement), SearchPattern::Regex(regexp, _)) => {
+            Some(Arc::new(Replacer::new(regexp.clone(), replacement)?))
+        }
+        _ => None,
+    };
+    let width = match term_size::dimensions() {
+        // Lines are not trimmed in passthrough mode
+        Some((width, _)) if !args.passthrough => width,
+        _ => usize::MAX,
+    };
+    let tpool = match args.threads {
+        Some(threads) => ThreadPool::builder().pool_size(threads.get()).create()?,
+        None => ThreadPool::new()?,
     };
-    let tpool = ThreadPool::new()?;
     let filter_patterns = {
         let mut filter_patterns = args.filter_patterns.clone();
         filter_patterns.extend(args.file_type_filters.iter().map(|e| format!("*.{}", e)));
@@ -168,51 +1168,85 @@ fn main() -> Result<(), Error> {
         }
         filter_patterns
     };
-    let file_filters = Filters::new(&filter_patterns)?;
-
-    // Special case: `-L` is the same as `-l -v`
-    let invert_match = if args.files_without_match {
-        if args.invert_match {
-            anyhow::bail!("incompatible flags: -L and -v");
-        }
-        true
+    let glob_case_insensitive = args.glob_case_insensitive || args.ignore_case;
+    let path_regex = match &args.path_regex {
+        Some(path_regex) => Some(
+            RegexBuilder::new(path_regex)
+                .case_insensitive(glob_case_insensitive)
+                .build()?,
+        ),
+        None => None,
+    };
+    let file_filters =
+        Filters::new(&filter_patterns, glob_case_insensitive)?.path_regex(path_regex);
+    let preprocessor = match &args.pre {
+        Some(command) => Some(Preprocessor::new(command, &args.pre_globs)?),
+        None => None,
+    };
+    let no_mmap = if args.no_mmap_globs.is_empty() {
+        None
     } else {
-        args.invert_match
+        Some(Filters::new(&args.no_mmap_globs, false)?)
     };
-    let path_only = if args.files_without_match {
-        if args.files_with_match {
-            anyhow::bail!("incompatible flags: -L and -l");
-        }
-        true
+
+    // Special case: `-L` is the same as `-l -v`
+    let invert_match = args.files_without_match || args.invert_match;
+    let path_only = args.files_without_match || args.files_with_match || args.files_zero_match;
+    let filename_match = if args.filename_only {
+        Some(FilenameMatch::Basename)
+    } else if args.filename_match {
+        Some(FilenameMatch::Path)
     } else {
-        args.files_with_match
+        None
     };
+    let path_only = path_only || filename_match.is_some();
 
     let matcher = {
         // Some fun stuff:
         // 1. https://github.com/rust-lang/rust/issues/22340
         // 2. https://github.com/rust-lang/rust/issues/26085
         // 3. https://github.com/rust-lang/rust/issues/29625
-        let regexp = regexp;
+        let search_pattern = search_pattern;
+        // Only `-L` can skip a file with a match, `-v` still prints the other lines
+        let files_without_match = args.files_without_match;
         move |line: &str, options| -> Option<Vec<Match>> {
-            let invert_option = if invert_match {
-                Some(vec![Match::new(0, line.len())])
-            } else {
-                None
-            };
             match options {
-                MatcherOptions::Fuzzy => {
-                    let result = regexp
-                        .shortest_match(line)
-                        .map(|pos| vec![Match::new(0, pos)]);
-                    result.xor(invert_option)
+                MatcherOptions::Fuzzy if invert_match && !files_without_match => {
+                    Some(vec![Match::new(0, 0)])
                 }
+                MatcherOptions::Fuzzy => match &search_pattern {
+                    SearchPattern::Regex(_, fuzzy_regexp) => {
+                        let result = fuzzy_regexp
+                            .shortest_match(line)
+                            .map(|pos| vec![Match::new(0, pos)]);
+                        result.xor(invert_match.then(|| vec![Match::new(0, line.len())]))
+                    }
+                    // A glob can't tell whether any line of the content matches
+                    SearchPattern::Glob(..) => Some(vec![Match::new(0, 0)]),
+                },
                 MatcherOptions::Exact(max) => {
                     let mut matches = vec![];
-                    for (i, m) in regexp.find_iter(line).enumerate() {
-                        matches.push(Match::new(m.start(), m.end()));
-                        if i + 1 == max {
-                            break;
+                    match &search_pattern {
+                        SearchPattern::Regex(regexp, _) if capture == 0 => {
+                            for (i, m) in regexp.find_iter(line).enumerate() {
+                                matches.push(Match::new(m.start(), m.end()));
+                                if i + 1 == max {
+                                    break;
+                                }
+                            }
+                        }
+                        SearchPattern::Regex(regexp, _) => {
+                            // Matches where the group does not participate are skipped
+                            let groups = regexp
+                                .captures_iter(line)
+                                .filter_map(|captures| captures.get(capture))
+                                .take(max);
+                            matches.extend(groups.map(|m| Match::new(m.start(), m.end())));
+                        }
+                        SearchPattern::Glob(glob_pattern, options) => {
+                            if glob_pattern.matches_with(line, *options) {
+                                matches.push(Match::new(0, line.len()));
+                            }
                         }
                     }
                     if matches.is_empty() {
@@ -220,104 +1254,369 @@ fn main() -> Result<(), Error> {
                     } else {
                         Some(matches)
                     }
-                    .xor(invert_option)
+                    // A selected non-matching line has nothing to highlight
+                    .xor(invert_match.then(Vec::new))
                 }
             }
         }
     };
+    let match_limit = args.max_matches_total.map(MatchLimit::new);
+    let matcher: Matcher = Arc::new(Box::new(matcher));
+    let matcher = match args.max_line_length {
+        Some(max) => skip_long_lines(matcher, max),
+        None => matcher,
+    };
+    let matcher = if args.unique {
+        Unique::default().filter(matcher)
+    } else {
+        matcher
+    };
+    let matcher = match &match_limit {
+        Some(match_limit) => match_limit.limit(matcher),
+        None => matcher,
+    };
     let display = {
         let no_color = args.no_color || args.no_colour;
-        move |path_format: PathFormat| {
+        move |path_format: PathFormat, no_path: bool| {
+            let path_format: PathFormat = match args.path_separator {
+                Some(separator) => Arc::new(Box::new(move |entry: &Path| -> String {
+                    paths::replace_separator(&path_format(entry), path::MAIN_SEPARATOR, separator)
+                })),
+                None => path_format,
+            };
             DisplayTerminal::new(
                 width,
-                if path_only {
+                if json {
+                    Format::Json {
+                        path_only,
+                        count: args.count,
+                    }
+                } else if path_only || args.files {
                     Format::PathOnly { colour: !no_color }
+                } else if !args.fields.is_empty() {
+                    Format::Fields {
+                        fields: args.fields.clone(),
+                        zero_based_lno: args.zero_based_lines,
+                        show_whitespace: args.show_whitespace,
+                        offsets: args.offsets,
+                    }
                 } else {
                     Format::Rich {
                         colour: !no_color,
                         match_only: args.match_only,
-                        no_path: args.no_path,
-                        no_lno: args.no_lno || args.count || args.no_path,
+                        no_path: no_path || args.no_prefix_on_matches,
+                        no_lno: args.no_lno
+                            || (args.count && !args.match_only)
+                            || count_unit.is_some()
+                            || args.no_path
+                            || args.no_prefix_on_matches,
+                        lno_width: args.line_number_width,
+                        zero_based_lno: args.zero_based_lines,
+                        show_whitespace: args.show_whitespace,
+                        wrap: args.wrap,
                     }
                 },
                 path_format,
-                Arc::new(StdoutWriter::new()),
+                writer.clone(),
             )
+            .filename_once(args.filename_once)
+            .heading_path(args.heading_path.unwrap_or(HeadingPath::Relative))
         }
     };
+    // The --exclude-from patterns with a fixed root are the same for all the searched paths
+    let mut anchored_ignore_patterns = None;
     let force_ignore_patterns = {
-        let mut force_ignore_patterns = vec![GIT_DIR.to_owned() + "/"];
+        let mut force_ignore_patterns = if args.no_ignore {
+            vec![]
+        } else {
+            vec![GIT_DIR.to_owned() + "/"]
+        };
         force_ignore_patterns.extend(args.force_ignore_patterns);
+        for path in &args.exclude_from {
+            let content = fs::read_to_string(path).map_err(|e| {
+                Error::new(e).context(format!("failed to read '{}'", path.display()))
+            })?;
+            match &args.ignore_file_root {
+                Some(root) => {
+                    let root = root.canonicalize().map_err(|e| {
+                        Error::new(e).context(format!("failed to resolve '{}'", root.display()))
+                    })?;
+                    let patterns = Patterns::from_lines(root.to_str().unwrap(), &content);
+                    anchored_ignore_patterns
+                        .get_or_insert_with(Patterns::default)
+                        .extend(&patterns);
+                }
+                // Blank lines and comments are skipped along with the patterns
+                None => force_ignore_patterns.extend(content.lines().map(str::to_owned)),
+            }
+        }
         force_ignore_patterns
     };
-    for path in paths {
+    let cwd = if args.relative {
+        Some(env::current_dir()?.canonicalize()?)
+    } else {
+        None
+    };
+    let context = args.before.is_some() || args.after.is_some() || args.context_paragraph;
+    let grep = if let Some(unit) = count_unit {
+        grep::grep_units(unit)
+    } else if args.summary_only {
+        grep::grep_silent()
+    } else if args.count_matches {
+        grep::grep_count_matches(args.include_zero)
+    } else if args.count_percent {
+        grep::grep_count_percent(args.include_zero)
+    } else if args.count {
+        if args.match_only {
+            grep::grep_count_per_line()
+        } else {
+            grep::grep_count(args.include_zero)
+        }
+    } else if args.files_zero_match {
+        grep::grep_files_zero_match()
+    } else if path_only {
+        if invert_match {
+            grep::grep_matches_all_lines()
+        } else {
+            grep::grep_matches_once()
+        }
+    } else if args.multiline {
+        grep::grep_multiline()
+    } else if args.passthrough {
+        grep::grep_passthrough()
+    } else if context {
+        // A paragraph stops at a blank line unless -A limits it first
+        let after = match (args.after, args.context_paragraph) {
+            (None, true) => usize::MAX,
+            (after, _) => after.unwrap_or(0),
+        };
+        grep::grep_with_context(args.before.unwrap_or(0), after, args.context_paragraph)
+    } else if let Some((header, section)) = section {
+        grep::grep_section(header, section)
+    } else if let Some(mode) = args.git_diff {
+        let mode = mode.unwrap_or(GitDiffMode::Tracked);
+        grep::grep_changed(Arc::new(GitDiff::new(&paths, mode)?))
+    } else if let Some(replacer) = &replacer {
+        if args.write {
+            grep::grep_write(replacer.clone())
+        } else {
+            grep::grep_replace(replacer.clone())
+        }
+    } else {
+        grep::grep()
+    };
+    let stats = Arc::new(Stats::default());
+    let git_tracked = if args.git_tracked {
+        Some(Arc::new(GitTracked::new(&paths)?))
+    } else {
+        None
+    };
+    let now = SystemTime::now();
+    let ago = |duration| now.checked_sub(duration).unwrap_or(SystemTime::UNIX_EPOCH);
+    let modified_after = args.changed_within.map(ago);
+    let modified_before = args.changed_before.map(ago);
+    let on_file: Option<OnFile> = if log_enabled!(Level::Debug) {
+        Some(Arc::new(|event: FileEvent| match event {
+            FileEvent::Started(path) => debug!("Searching '{}'", path.display()),
+            FileEvent::Finished(path, Some(count)) => {
+                debug!("Searched '{}': {} matches", path.display(), count)
+            }
+            FileEvent::Finished(path, None) => debug!("Skipped '{}'", path.display()),
+        }))
+    } else {
+        None
+    };
+    let on_considered: Option<OnConsidered> = if log_enabled!(Level::Trace) {
+        Some(Arc::new(|path: &Path, decision: Decision| {
+            trace!("{:?} '{}'", decision, path.display())
+        }))
+    } else {
+        None
+    };
+    // Not applied to stdin, which may wait for its input
+    let with_timeout = |grep: grep::Grep| match args.timeout_per_file {
+        Some(timeout) => grep::with_timeout(grep, Duration::from_millis(timeout)),
+        None => grep,
+    };
+    let mut failed = false;
+    let mut ignore_cache = IgnoreCache::new(!args.no_negated_ignore);
+    let ignore_scan_roots = args
+        .ignore_scan_roots
+        .iter()
+        .map(|root| {
+            root.canonicalize().map_err(|e| {
+                Error::new(e).context(format!("failed to resolve '{}'", root.display()))
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let sorted_outputs = SortedOutputs::default();
+    let mut last_walker = None;
+    for path in &paths {
         let path = path.as_path();
         // See some fun at https://github.com/rust-lang/rfcs/issues/2208
         let prefix = path_clean::clean(path.to_str().unwrap());
-        let prefix = match fs::symlink_metadata(path) {
-            Ok(meta) if meta.is_dir() => prefix + &path::MAIN_SEPARATOR.to_string(),
+        // Symlinks are followed as the walk starts from the canonical path,
+        // a link to a directory needs the separator as well
+        let meta = fs::metadata(path);
+        let prefix = match &meta {
+            Ok(meta) if meta.is_dir() => prefix + path::MAIN_SEPARATOR_STR,
             _ => prefix,
         };
+        // Like grep, a single file searched on its own is not named
+        let single_file =
+            paths.len() == 1 && !search_stdin && meta.is_ok_and(|meta| meta.is_file());
         let fpath = match path.canonicalize() {
             Ok(path) => path,
             Err(err) => {
-                anyhow::bail!("failed to open path: {}", err);
+                error!("Failed to open path '{}': {}", path.display(), err);
+                failed = true;
+                continue;
             }
         };
-        let path_format = {
+        let repo_root = if args.repo_relative {
+            Walker::find_repo_root(&fpath)
+        } else {
+            None
+        };
+        let path_format: PathFormat = if args.absolute {
+            Arc::new(Box::new(|entry: &Path| -> String {
+                entry.to_str().unwrap().to_owned()
+            }))
+        } else if let Some(cwd) = &cwd {
+            let cwd = cwd.clone();
+            Arc::new(Box::new(move |entry: &Path| -> String {
+                paths::relative_to(entry, &cwd).to_str().unwrap().to_owned()
+            }))
+        } else if let Some(repo_root) = repo_root {
+            Arc::new(Box::new(move |entry: &Path| -> String {
+                paths::relative_to(entry, &repo_root)
+                    .to_str()
+                    .unwrap()
+                    .to_owned()
+            }))
+        } else {
             let fpath = fpath.clone();
-            move |entry: &Path| -> String {
-                let entry = entry.strip_prefix(&fpath).unwrap();
-                prefix.clone() + entry.to_str().unwrap()
+            Arc::new(Box::new(move |entry: &Path| -> String {
+                match entry.strip_prefix(&fpath) {
+                    Ok(entry) => prefix.clone() + entry.to_str().unwrap(),
+                    // Symlinks may lead out of the searched path
+                    Err(_) => entry.to_str().unwrap().to_owned(),
+                }
+            }))
+        };
+        let display = display(
+            path_format,
+            args.no_path || (single_file && !args.with_path),
+        );
+        let force_ignore_patterns = {
+            let mut patterns =
+                Patterns::new(fpath.as_path().to_str().unwrap(), &force_ignore_patterns);
+            if let Some(anchored_ignore_patterns) = &anchored_ignore_patterns {
+                patterns.extend(anchored_ignore_patterns);
             }
+            patterns
         };
-        let display = display(Arc::new(Box::new(path_format)));
-        let force_ignore_patterns =
-            Patterns::new(fpath.as_path().to_str().unwrap(), &force_ignore_patterns);
         let ignore_patterns = Patterns::new(fpath.as_path().to_str().unwrap(), &[]);
-        let ignore_patterns =
-            if let Some(mut parent_patterns) = Walker::find_ignore_patterns_in_parents(&fpath) {
-                parent_patterns.extend(&ignore_patterns);
-                parent_patterns
-            } else {
-                ignore_patterns
-            };
-        let grep = if args.count {
-            if invert_match {
-                anyhow::bail!("incompatible flags: -c and -v");
-            }
-            grep::grep_count()
-        } else if path_only {
-            if invert_match {
-                grep::grep_matches_all_lines()
-            } else {
-                grep::grep_matches_once()
-            }
-        } else if args.before.is_some() || args.after.is_some() {
-            grep::grep_with_context(args.before.unwrap_or(0), args.after.unwrap_or(0))
+        let parent_patterns = if args.no_ignore {
+            None
+        } else {
+            Walker::find_ignore_patterns_in_parents(&fpath, &ignore_scan_roots, &mut ignore_cache)
+        };
+        let ignore_patterns = if let Some(mut parent_patterns) = parent_patterns {
+            parent_patterns.extend(&ignore_patterns);
+            parent_patterns
         } else {
-            grep::grep()
+            ignore_patterns
         };
-        let walker =
-            WalkerBuilder::new(grep, Arc::new(Box::new(matcher.clone())), Arc::new(display))
-                .thread_pool(tpool.clone())
-                .ignore_patterns(ignore_patterns)
-                .force_ignore_patterns(force_ignore_patterns)
-                .file_filters(file_filters.clone())
-                .ignore_symlinks(args.ignore_symlinks)
-                .print_file_separator(args.before.is_some() || args.after.is_some())
-                .build();
+        // Modes printing only paths or counts are safe for binary files
+        let binary_grep = if !args.binary {
+            None
+        } else if args.count || path_only {
+            Some(grep.clone())
+        } else {
+            Some(grep::grep_binary())
+        };
+        let binary_grep = binary_grep.map(with_timeout);
+        let walker = WalkerBuilder::new(
+            with_timeout(grep.clone()),
+            matcher.clone(),
+            Arc::new(display),
+        )
+        .thread_pool(tpool.clone())
+        .ignore_patterns(ignore_patterns)
+        .force_ignore_patterns(force_ignore_patterns)
+        .ignore_files(!args.no_ignore)
+        .negated_ignore(!args.no_negated_ignore)
+        .file_filters(file_filters.clone())
+        .ignore_symlinks(args.ignore_symlinks)
+        .match_limit(match_limit.clone())
+        .filename_match(filename_match)
+        .filename_match_type(args.filename_match_type.unwrap_or(EntryType::File))
+        .list_files(args.files)
+        .skip_binary_ext(args.skip_binary_ext, &args.binary_exts)
+        .git_tracked(git_tracked.clone())
+        .binary_grep(binary_grep)
+        .search_zip(args.search_zip)
+        .preprocessor(preprocessor.clone())
+        .encoding(args.encoding)
+        .encoding_fallback(args.encoding_fallback)
+        .no_mmap(no_mmap.clone())
+        .mmap_populate(args.mmap_populate)
+        .on_file(on_file.clone())
+        .on_considered(on_considered.clone())
+        .print_file_separator(context)
+        .separator_after_last(args.separator_after_last)
+        .line_buffered(args.line_buffered)
+        .modified_after(modified_after)
+        .modified_before(modified_before)
+        .sort_by(args.sort_by.or(args.sort_by_reverse))
+        .sort_reverse(args.sort_by_reverse.is_some())
+        .sorted_outputs(sorted_outputs.clone())
+        .stats(stats.clone());
+        #[cfg(feature = "archive")]
+        let walker = walker.search_archive(args.search_archive);
+        let walker = walker.build();
         walker.walk(&fpath);
+        last_walker = Some(walker);
+    }
+    // The output of all the paths is sorted together
+    if let Some(walker) = last_walker {
+        walker.flush_sorted();
     }
-    if stdin.is_readable() {
+    if search_stdin {
         let path_format = |entry: &Path| -> String { entry.to_str().unwrap().to_owned() };
-        let display = display(Arc::new(Box::new(path_format)));
-        grep::grep()(
-            Arc::new(stdin),
-            Arc::new(Box::new(matcher)),
-            Arc::new(display),
-        );
+        // Like grep, a single implicit input is not named
+        let no_path = args.no_path || (paths.is_empty() && !args.with_path);
+        let display = display(Arc::new(Box::new(path_format)), no_path);
+        let count = grep(Arc::new(stdin), matcher, Arc::new(display));
+        stats.record(0, Some(count));
     }
 
+    if args.stats {
+        eprintln!(
+            "{} files searched, {} skipped, {} bytes scanned in {:.3}s",
+            stats.files_searched(),
+            stats.files_skipped(),
+            stats.bytes_scanned(),
+            stats.elapsed().as_secs_f64()
+        );
+    }
+    if args.summary_only {
+        println!(
+            "{} files matched, {} searched, {} matching lines",
+            stats.files_matched(),
+            stats.files_searched(),
+            stats.matches()
+        );
+    }
+    if count_unit.is_some() {
+        println!("{} total", stats.matches());
+    }
+    if let (Some(replacer), true) = (&replacer, args.write) {
+        eprintln!("{} files modified", replacer.modified());
+    }
+    if failed {
+        anyhow::bail!("failed to open some of the paths");
+    }
     Ok(())
 }
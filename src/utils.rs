@@ -1,10 +1,18 @@
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod decoded;
 pub mod display;
 pub mod filters;
+pub mod gitdiff;
 pub mod grep;
+pub mod gzipped;
 pub mod lines;
 pub mod mapped;
 pub mod matcher;
+pub mod paths;
 pub mod patterns;
+pub mod preprocessor;
+pub mod replace;
 pub mod stdin;
 pub mod walker;
 pub mod writer;
@@ -0,0 +1,156 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use flate2::read::MultiGzDecoder;
+use log::debug;
+
+use crate::utils::lines::{LineIterator, Lines, LinesReader};
+
+/// Whether the file is a `.tar`, `.tar.gz` or `.tgz` archive
+pub fn is_archive(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// A text file of an archive, named `archive.tar:member`
+pub struct Member {
+    path: PathBuf,
+    content: Content,
+}
+
+/// The member content, shared with the lines iterators rather than copied
+#[derive(Clone)]
+struct Content(Arc<String>);
+
+impl AsRef<[u8]> for Content {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl LinesReader for Member {
+    fn supports_map(&self) -> bool {
+        true
+    }
+
+    fn map(&self) -> anyhow::Result<&str> {
+        Ok(&self.content.0)
+    }
+
+    fn lines(&self) -> anyhow::Result<Box<LineIterator>> {
+        Ok(Box::new(Lines::new(
+            io::Cursor::new(self.content.clone()),
+            self.path.clone(),
+        )))
+    }
+
+    fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+/// Reads the text members of the archive. Members are held in memory one by one,
+/// so searching an archive with a large member takes as much memory as the member.
+pub fn members(path: &Path, mut on_member: impl FnMut(Member)) -> io::Result<()> {
+    let file = File::open(path)?;
+    let name = path.file_name().unwrap().to_string_lossy();
+    let reader: Box<dyn Read> = if name.ends_with(".tar") {
+        Box::new(io::BufReader::new(file))
+    } else {
+        Box::new(MultiGzDecoder::new(io::BufReader::new(file)))
+    };
+    read_members(reader, path, &mut on_member)
+}
+
+fn read_members(
+    reader: impl Read,
+    path: &Path,
+    on_member: &mut dyn FnMut(Member),
+) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        if !kind.is_file() && !kind.is_contiguous() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        // Not sized from the header, which may be corrupt
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        if content_inspector::inspect(&content).is_binary() {
+            debug!("Skipping binary member '{}:{}'", path.display(), name);
+            continue;
+        }
+        on_member(Member {
+            path: PathBuf::from(format!("{}:{}", path.display(), name)),
+            content: Content(Arc::new(String::from_utf8_lossy(&content).into_owned())),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn append(tar: &mut tar::Builder<Vec<u8>>, name: &str, content: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, name, content).unwrap();
+    }
+
+    fn read(tar: &[u8]) -> io::Result<Vec<(PathBuf, String)>> {
+        let mut members = Vec::new();
+        read_members(tar, Path::new("x.tar"), &mut |member| {
+            members.push((member.path.clone(), member.map().unwrap().to_owned()))
+        })?;
+        Ok(members)
+    }
+
+    #[test]
+    fn tar() {
+        let long = "d/".repeat(60) + "long.txt";
+        let mut tar = tar::Builder::new(Vec::new());
+        append(&mut tar, "a.txt", b"foo\nbar\n");
+        let mut dir = tar::Header::new_gnu();
+        dir.set_entry_type(tar::EntryType::Directory);
+        dir.set_size(0);
+        tar.append_data(&mut dir, "dir/", io::empty()).unwrap();
+        append(&mut tar, "bin", b"\0\x01");
+        append(&mut tar, &long, b"baz");
+        let tar = tar.into_inner().unwrap();
+
+        assert_eq!(
+            vec![
+                (PathBuf::from("x.tar:a.txt"), "foo\nbar\n".to_owned()),
+                (PathBuf::from(format!("x.tar:{}", long)), "baz".to_owned()),
+            ],
+            read(&tar).unwrap()
+        );
+        assert!(is_archive(Path::new("x.tgz")));
+        assert!(!is_archive(Path::new("x.gz")));
+    }
+
+    #[test]
+    fn corrupt_size() {
+        let mut header = tar::Header::new_gnu();
+        header.set_path("a.txt").unwrap();
+        header.set_size(3);
+        // The largest base-256 size
+        header.as_gnu_mut().unwrap().size = [0xff; 12];
+        header.set_cksum();
+        let mut tar = header.as_bytes().to_vec();
+        tar.extend(b"foo");
+        tar.resize(1024, 0);
+        assert!(read(&tar).is_err());
+    }
+}
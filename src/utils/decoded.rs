@@ -0,0 +1,119 @@
+use std::{io, path::PathBuf, str::FromStr};
+
+use encoding_rs::UTF_8;
+use log::debug;
+use once_cell::sync::Lazy;
+use regex::bytes::Regex;
+
+use crate::utils::lines::{LineIterator, Lines, LinesReader};
+
+/// Number of leading lines searched for a coding declaration, as Python and Emacs do
+const HINT_LINES: usize = 2;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Encoding {
+    /// Sniffs editor coding declarations, falls back to UTF-8
+    Auto,
+    Label(&'static encoding_rs::Encoding),
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Encoding::Auto);
+        }
+        match for_label(s) {
+            Some(encoding) => Ok(Encoding::Label(encoding)),
+            None => anyhow::bail!("unknown encoding '{}'", s),
+        }
+    }
+}
+
+/// Also accepts the spellings editors use, e.g. `latin-1` or `utf_8`
+fn for_label(label: &str) -> Option<&'static encoding_rs::Encoding> {
+    let lookup = |label: String| encoding_rs::Encoding::for_label(label.as_bytes());
+    lookup(label.to_owned())
+        .or_else(|| lookup(label.replace('_', "-")))
+        .or_else(|| lookup(label.replace(['-', '_'], "")))
+}
+
+static CODING: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?:coding[:=]|fenc=)[ \t]*([-\w.]+)").unwrap());
+
+/// Looks for Emacs/Python `coding: <name>` and vim `fileencoding=<name>` in the first lines
+fn sniff(content: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+    content
+        .split(|c| *c == b'\n')
+        .take(HINT_LINES)
+        .find_map(|line| CODING.captures(line))
+        .and_then(|captures| std::str::from_utf8(&captures[1]).ok().and_then(for_label))
+}
+
+/// Content of a file decoded to UTF-8
+pub struct Decoded {
+    path: PathBuf,
+    content: String,
+}
+
+impl Decoded {
+    pub fn new(path: PathBuf, content: &[u8], encoding: Encoding) -> Self {
+        let encoding = match encoding {
+            Encoding::Auto => sniff(content).unwrap_or(UTF_8),
+            Encoding::Label(encoding) => encoding,
+        };
+        debug!("Decoding '{}' as {}", path.display(), encoding.name());
+        let (content, _, _) = encoding.decode(content);
+        Decoded {
+            path,
+            content: content.into_owned(),
+        }
+    }
+}
+
+impl LinesReader for Decoded {
+    fn supports_map(&self) -> bool {
+        true
+    }
+
+    fn map(&self) -> anyhow::Result<&str> {
+        Ok(&self.content)
+    }
+
+    fn lines(&self) -> anyhow::Result<Box<LineIterator>> {
+        Ok(Box::new(Lines::new(
+            io::Cursor::new(self.content.clone()),
+            self.path.clone(),
+        )))
+    }
+
+    fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(content: &[u8], encoding: &str) -> String {
+        let encoding = encoding.parse().unwrap();
+        Decoded::new(PathBuf::from("f"), content, encoding).content
+    }
+
+    #[test]
+    fn coding_hints() {
+        let emacs = b"; -*- coding: latin-1 -*-\ncaf\xe9\n";
+        assert_eq!("; -*- coding: latin-1 -*-\ncafé\n", decode(emacs, "auto"));
+        let python = b"#!/usr/bin/env python\n# -*- coding: iso-8859-15 -*-\n\xa4\n";
+        assert!(decode(python, "auto").ends_with("€\n"));
+        let vim = b"\" vim: set fileencoding=latin1 :\n\xe9\n";
+        assert!(decode(vim, "auto").ends_with("é\n"));
+        // Hints past the first lines are ignored
+        let late = b"\n\n# coding: latin-1\n\xe9\n";
+        assert!(decode(late, "auto").ends_with("\u{fffd}\n"));
+        assert_eq!("é", decode(b"\xe9", "latin1"));
+        assert!("klingon".parse::<Encoding>().is_err());
+    }
+}
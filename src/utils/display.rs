@@ -1,12 +1,19 @@
-use std::{cmp, path::Path, sync::Arc};
+use std::{
+    cmp, iter,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
 
-use ansi_term::Colour;
+use ansi_term::{Colour, Style};
+use serde_json::json;
 
 use crate::utils::matcher::Match;
 use crate::utils::writer::Writer;
 
 type Range = std::ops::Range<usize>;
 
+/// A line to display along with its number and the byte ranges of the matches within it
 pub struct DisplayContext<'a> {
     lno: usize,
     line: String,
@@ -34,10 +41,64 @@ impl<'a> DisplayContext<'a> {
         ctx.lno_sep = lno_sep;
         ctx
     }
+
+    /// 1-based number of the line in the file
+    pub fn line_number(&self) -> usize {
+        self.lno
+    }
+
+    /// The line without its terminator
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Byte ranges of the matches within the line as read from the file, in order.
+    /// Display transformations such as visible whitespace never alter them.
+    pub fn matches(&self) -> &[Match] {
+        &self.needle
+    }
+}
+
+/// How the walker would read a file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileKind {
+    Empty,
+    Text,
+    Binary,
+    Gzip,
+    Preprocessed,
+    #[cfg(feature = "archive")]
+    Archive,
+}
+
+impl FileKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::Empty => "empty",
+            FileKind::Text => "text",
+            FileKind::Binary => "binary",
+            FileKind::Gzip => "gzip",
+            FileKind::Preprocessed => "preprocessed",
+            #[cfg(feature = "archive")]
+            FileKind::Archive => "archive",
+        }
+    }
+}
+
+pub struct FileInfo {
+    pub size: usize,
+    pub kind: FileKind,
+    pub mapped: bool,
 }
 
 pub trait Display: Send + Sync {
     fn display(&self, path: &Path, context: Option<DisplayContext>);
+    fn file_info(&self, path: &Path, info: &FileInfo);
+    /// Whether the output of every file is to be enclosed by `file_begin` and `file_end`
+    fn file_events(&self) -> bool;
+    fn file_begin(&self, path: &Path);
+    fn file_end(&self, path: &Path, matches: usize, bytes: usize);
+    fn binary_file_matches(&self, path: &Path);
     fn file_separator(&self);
     fn match_separator(&self);
     fn writer(&self) -> Arc<dyn Writer>;
@@ -46,8 +107,40 @@ pub trait Display: Send + Sync {
 
 pub type PathFormat = Arc<Box<dyn Fn(&Path) -> String + Send + Sync>>;
 
+/// How the file is named on its first line with `filename_once`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeadingPath {
+    /// Absolute path
+    Full,
+    /// As formatted by the `PathFormat`, relative to the search root unless told otherwise
+    Relative,
+    Basename,
+}
+
+impl FromStr for HeadingPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(HeadingPath::Full),
+            "relative" => Ok(HeadingPath::Relative),
+            "basename" => Ok(HeadingPath::Basename),
+            _ => anyhow::bail!("unknown heading path '{}'", s),
+        }
+    }
+}
+
 pub trait OutputFormat: Send + Sync {
     fn format(&self, width: usize, path: &str, context: Option<DisplayContext>) -> String;
+    /// The same format omitting the path of the lines, `None` if the path can't be omitted
+    fn without_path(&self) -> Option<Self>
+    where
+        Self: Sized;
+    fn binary_file_matches(&self, path: &str) -> String;
+    fn file_info(&self, path: &str, info: &FileInfo) -> String;
+    fn file_events(&self) -> bool;
+    fn file_begin(&self, path: &str) -> String;
+    fn file_end(&self, path: &str, matches: usize, bytes: usize) -> String;
     fn file_separator(&self) -> String;
     fn match_separator(&self) -> String;
 }
@@ -61,6 +154,9 @@ where
     format: T,
     path_format: PathFormat,
     writer: Arc<dyn Writer>,
+    filename_once: bool,
+    heading_path: HeadingPath,
+    last_path: Arc<Mutex<Option<String>>>,
 }
 
 impl<T> DisplayTerminal<T>
@@ -73,6 +169,32 @@ where
             format,
             path_format,
             writer,
+            filename_once: false,
+            heading_path: HeadingPath::Relative,
+            last_path: Default::default(),
+        }
+    }
+
+    /// Names the file on the first of consecutive lines of the file only
+    pub fn filename_once(mut self, filename_once: bool) -> Self {
+        self.filename_once = filename_once;
+        self
+    }
+
+    /// How the file is named on its first line with `filename_once`
+    pub fn heading_path(mut self, heading_path: HeadingPath) -> Self {
+        self.heading_path = heading_path;
+        self
+    }
+
+    fn heading(&self, path: &Path, formatted: String) -> String {
+        match self.heading_path {
+            HeadingPath::Full => path.to_string_lossy().into_owned(),
+            HeadingPath::Relative => formatted,
+            HeadingPath::Basename => match path.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => formatted,
+            },
         }
     }
 }
@@ -82,9 +204,48 @@ where
     T: OutputFormat + Clone + 'static,
 {
     fn display(&self, path: &Path, context: Option<DisplayContext>) {
+        let formatted = (self.path_format)(path);
+        let (path, without_path) = if self.filename_once && context.is_some() {
+            let mut last_path = self.last_path.lock().unwrap();
+            if last_path.as_deref() == Some(formatted.as_str()) {
+                (formatted, self.format.without_path())
+            } else {
+                *last_path = Some(formatted.clone());
+                (self.heading(path, formatted), None)
+            }
+        } else {
+            (formatted, None)
+        };
+        let formated = match without_path {
+            Some(format) => format.format(self.width, "", context),
+            None => self.format.format(self.width, &path, context),
+        };
+        self.writer.write(&formated);
+    }
+
+    fn binary_file_matches(&self, path: &Path) {
+        let formated = self.format.binary_file_matches(&(self.path_format)(path));
+        self.writer.write(&formated);
+    }
+
+    fn file_info(&self, path: &Path, info: &FileInfo) {
+        let formated = self.format.file_info(&(self.path_format)(path), info);
+        self.writer.write(&formated);
+    }
+
+    fn file_events(&self) -> bool {
+        self.format.file_events()
+    }
+
+    fn file_begin(&self, path: &Path) {
+        let formated = self.format.file_begin(&(self.path_format)(path));
+        self.writer.write(&formated);
+    }
+
+    fn file_end(&self, path: &Path, matches: usize, bytes: usize) {
         let formated = self
             .format
-            .format(self.width, &(self.path_format)(path), context);
+            .file_end(&(self.path_format)(path), matches, bytes);
         self.writer.write(&formated);
     }
 
@@ -103,12 +264,16 @@ where
     }
 
     fn with_writer(&self, writer: Arc<dyn Writer>) -> Arc<dyn Display> {
-        Arc::new(DisplayTerminal::new(
-            self.width,
-            self.format.clone(),
-            self.path_format.clone(),
-            writer,
-        ))
+        Arc::new(
+            DisplayTerminal::new(
+                self.width,
+                self.format.clone(),
+                self.path_format.clone(),
+                writer,
+            )
+            .filename_once(self.filename_once)
+            .heading_path(self.heading_path),
+        )
     }
 }
 
@@ -119,10 +284,146 @@ pub enum Format {
         match_only: bool,
         no_path: bool,
         no_lno: bool,
+        /// Line numbers are right-aligned to this width
+        lno_width: usize,
+        /// Line numbers are displayed starting from 0
+        zero_based_lno: bool,
+        /// Tabs and trailing spaces are replaced with visible markers
+        show_whitespace: bool,
+        /// Long lines are wrapped over several output lines instead of being trimmed
+        wrap: bool,
     },
     PathOnly {
         colour: bool,
     },
+    /// One JSON object per line
+    Json {
+        /// Records carry the path only, e.g. with -l
+        path_only: bool,
+        /// Records carry the number of the file instead of a line, e.g. with -c
+        count: bool,
+    },
+    /// The fields in the given order separated by `:`, one line per match with `Field::Match`
+    Fields {
+        fields: Vec<Field>,
+        /// Line numbers are displayed starting from 0
+        zero_based_lno: bool,
+        /// Tabs and trailing spaces are replaced with visible markers
+        show_whitespace: bool,
+        offsets: Offsets,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Path,
+    /// Line number
+    Lno,
+    /// 1-based byte column of the match, of the first one unless `Field::Match` is selected
+    Col,
+    Line,
+    /// Text of the match
+    Match,
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(Field::Path),
+            "lno" => Ok(Field::Lno),
+            "col" => Ok(Field::Col),
+            "line" => Ok(Field::Line),
+            "match" => Ok(Field::Match),
+            _ => anyhow::bail!("unknown field '{}'", s),
+        }
+    }
+}
+
+/// The line `Field::Col` refers to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Offsets {
+    /// The line as read from the file
+    Original,
+    /// The line as printed, e.g. with visible whitespace
+    Displayed,
+}
+
+impl FromStr for Offsets {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(Offsets::Original),
+            "displayed" => Ok(Offsets::Displayed),
+            _ => anyhow::bail!("unknown offsets '{}'", s),
+        }
+    }
+}
+
+// A UTF-8 char is at most 4 bytes long, so a boundary is never more than 3 bytes away
+
+fn ceil_char_boundary(line: &str, offset: usize) -> usize {
+    (offset..cmp::min(offset + 4, line.len()))
+        .find(|offset| line.is_char_boundary(*offset))
+        .unwrap_or(line.len())
+}
+
+fn floor_char_boundary(line: &str, offset: usize) -> usize {
+    (offset.saturating_sub(3)..=offset)
+        .rev()
+        .find(|offset| line.is_char_boundary(*offset))
+        .unwrap_or(0)
+}
+
+/// Number of chars outside of the ANSI escape sequences
+fn visible_width(formatted: &str) -> usize {
+    let mut width = 0;
+    let mut chars = formatted.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Up to the final byte of the sequence, e.g. `m` of `\x1b[31m`
+            chars.find(|c| ('@'..='~').contains(c) && *c != '[');
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+const SPACE_MARKER: char = '·';
+const TAB_MARKER: char = '→';
+
+/// Replaces tabs and trailing spaces with markers, the needles are moved to the new offsets
+fn visible_whitespace(line: &str, needles: Vec<Match>) -> (String, Vec<Match>) {
+    let trailing = line.trim_end_matches([' ', '\t']).len();
+    let mut shown = String::with_capacity(line.len());
+    // Maps every byte offset of the line to its offset in the shown one
+    let mut offsets = Vec::with_capacity(line.len() + 1);
+    for (offset, c) in line.char_indices() {
+        offsets.extend(iter::repeat_n(shown.len(), c.len_utf8()));
+        match c {
+            '\t' => shown.push(TAB_MARKER),
+            ' ' if offset >= trailing => shown.push(SPACE_MARKER),
+            c => shown.push(c),
+        }
+    }
+    offsets.push(shown.len());
+    let needles = needles
+        .into_iter()
+        .map(|m| Match::new(offsets[m.start()], offsets[m.end()]))
+        .collect();
+    (shown, needles)
+}
+
+/// Dims the markers, only the intensity is reset afterwards (SGR 22)
+/// to keep the colour of an enclosing match
+fn dim_whitespace(formatted: &str) -> String {
+    let dim = |marker: char| format!("{}{}\x1b[22m", Style::new().dimmed().prefix(), marker);
+    formatted
+        .replace(SPACE_MARKER, &dim(SPACE_MARKER))
+        .replace(TAB_MARKER, &dim(TAB_MARKER))
 }
 
 impl Format {
@@ -180,14 +481,11 @@ impl Format {
             (needle.start, "")
         } else if needle.start > left_margin {
             let prefix = "[...] ";
-            let mut offset = needle.start - left_margin + prefix.len();
+            let offset = needle.start - left_margin + prefix.len();
             if offset >= needle.start {
                 (0, "")
             } else {
-                while line.get(offset..needle.start) == None {
-                    offset += 1;
-                }
-                (offset, prefix)
+                (ceil_char_boundary(line, offset), prefix)
             }
         } else {
             (0, "")
@@ -196,14 +494,11 @@ impl Format {
             (needle.end, "")
         } else if line.len() - needle.end > right_margin {
             let suffix = " [...]";
-            let mut offset = needle.end + right_margin - suffix.len();
+            let offset = needle.end + right_margin - suffix.len();
             if needle.end >= offset {
                 (line.len(), "")
             } else {
-                while line.get(needle.end..offset) == None {
-                    offset -= 1;
-                }
-                (offset, suffix)
+                (floor_char_boundary(line, offset), suffix)
             }
         } else {
             (line.len(), "")
@@ -225,6 +520,54 @@ impl Format {
         }
     }
 
+    /// Splits the line into rows of at most `width` chars, the first one being `first` chars
+    /// at most, a needle crossing a row boundary is highlighted on both rows
+    fn rich_format_wrapped(
+        &self,
+        first: usize,
+        width: usize,
+        line: &str,
+        needles: Vec<Range>,
+        colour: bool,
+    ) -> String {
+        let mut rows = Vec::new();
+        let mut start = 0;
+        while start < line.len() {
+            let row_width = if rows.is_empty() { first } else { width };
+            let end = line[start..]
+                .char_indices()
+                .nth(row_width)
+                .map_or(line.len(), |(offset, _)| start + offset);
+            // A row holds at least one char however narrow the terminal
+            let end = if end > start {
+                end
+            } else {
+                ceil_char_boundary(line, start + 1)
+            };
+            let mut row = String::with_capacity(end - start);
+            let mut last = start;
+            for needle in &needles {
+                let (what_start, what_end) =
+                    (cmp::max(needle.start, start), cmp::min(needle.end, end));
+                if what_start >= what_end {
+                    continue;
+                }
+                row.push_str(&line[last..what_start]);
+                let what = &line[what_start..what_end];
+                if colour {
+                    row.push_str(&Colour::Red.paint(what).to_string());
+                } else {
+                    row.push_str(what);
+                }
+                last = what_end;
+            }
+            row.push_str(&line[last..end]);
+            rows.push(row);
+            start = end;
+        }
+        rows.join("\n")
+    }
+
     fn rich_format_needles_only(
         &self,
         prefix: &str,
@@ -276,6 +619,55 @@ impl Format {
         }
     }
 
+    fn format_fields(
+        fields: &[Field],
+        zero_based_lno: bool,
+        show_whitespace: bool,
+        offsets: Offsets,
+        path: &str,
+        ctx: DisplayContext,
+    ) -> String {
+        // The original matches are kept for `Offsets::Original`
+        let (line, needles) = if show_whitespace {
+            visible_whitespace(&ctx.line, ctx.needle.clone())
+        } else {
+            (ctx.line.clone(), ctx.needle.clone())
+        };
+        // Lines selected by `-v` have no matches, they are printed once, without `Field::Match`
+        let indices: Vec<_> = if fields.contains(&Field::Match) && !needles.is_empty() {
+            (0..needles.len()).map(Some).collect()
+        } else {
+            vec![(!needles.is_empty()).then_some(0)]
+        };
+        let format = |index: Option<usize>| {
+            let needle = index.map(|index| &needles[index]);
+            let col = match offsets {
+                Offsets::Original => index.map(|index| &ctx.needle[index]),
+                Offsets::Displayed => needle,
+            };
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|field| match field {
+                    Field::Path => path.to_owned(),
+                    Field::Lno => if zero_based_lno {
+                        ctx.lno.saturating_sub(1)
+                    } else {
+                        ctx.lno
+                    }
+                    .to_string(),
+                    Field::Col => col.map_or(1, |m| m.start() + 1).to_string(),
+                    Field::Line => line.clone(),
+                    Field::Match => needle
+                        .map(|m| line[m.start()..m.end()].to_owned())
+                        .unwrap_or_default(),
+                })
+                .collect();
+            fields.join(":")
+        };
+        let output: Vec<_> = indices.into_iter().map(format).collect();
+        output.join("\n")
+    }
+
     fn separator(&self, separator: &str, code: u8) -> String {
         let colour = match self {
             Format::Rich { colour, .. } => *colour,
@@ -297,6 +689,10 @@ impl OutputFormat for Format {
                 match_only,
                 no_path,
                 no_lno,
+                lno_width,
+                zero_based_lno,
+                show_whitespace,
+                wrap,
             } => match context {
                 Some(ctx) => {
                     let prefix = if *no_path {
@@ -316,7 +712,12 @@ impl OutputFormat for Format {
                     let prefix = if *no_lno {
                         prefix
                     } else {
-                        let lno = ctx.lno.to_string();
+                        let lno = if *zero_based_lno {
+                            ctx.lno.saturating_sub(1)
+                        } else {
+                            ctx.lno
+                        };
+                        let lno = format!("{:>width$}", lno, width = lno_width);
                         if *colour {
                             format!(
                                 "{}{}{}",
@@ -325,7 +726,7 @@ impl OutputFormat for Format {
                                 Colour::Cyan.paint(ctx.lno_sep)
                             )
                         } else {
-                            format!("{}{}{}", prefix, ctx.lno, ctx.lno_sep)
+                            format!("{}{}{}", prefix, lno, ctx.lno_sep)
                         }
                     };
                     let prefix = if prefix.is_empty() {
@@ -333,19 +734,116 @@ impl OutputFormat for Format {
                     } else {
                         format!("{} ", prefix)
                     };
-                    let needles = ctx.needle.into_iter().map(Into::into).collect();
-                    if *match_only {
-                        self.rich_format_needles_only(&prefix, &ctx.line, needles, *colour)
+                    let (line, needles) = if *show_whitespace {
+                        visible_whitespace(&ctx.line, ctx.needle)
+                    } else {
+                        (ctx.line, ctx.needle)
+                    };
+                    let needles: Vec<Range> = needles.into_iter().map(Into::into).collect();
+                    // Context lines of `-o` are displayed whole, they have no matches
+                    let formatted = if *match_only && !needles.is_empty() {
+                        self.rich_format_needles_only(&prefix, &line, needles, *colour)
+                    } else if *wrap {
+                        let first = width.saturating_sub(visible_width(&prefix));
+                        let wrapped =
+                            self.rich_format_wrapped(first, width, &line, needles, *colour);
+                        format!("{}{}", prefix, wrapped)
+                    } else {
+                        self.rich_format(width - prefix.len(), &prefix, &line, needles, *colour)
+                    };
+                    if *show_whitespace && *colour {
+                        dim_whitespace(&formatted)
                     } else {
-                        self.rich_format(width - prefix.len(), &prefix, &ctx.line, needles, *colour)
+                        formatted
                     }
                 }
                 None => self.format_path(path, *colour),
             },
             Format::PathOnly { colour } => self.format_path(path, *colour),
+            Format::Fields {
+                fields,
+                zero_based_lno,
+                show_whitespace,
+                offsets,
+            } => match context {
+                Some(ctx) => Self::format_fields(
+                    fields,
+                    *zero_based_lno,
+                    *show_whitespace,
+                    *offsets,
+                    path,
+                    ctx,
+                ),
+                None => path.to_owned(),
+            },
+            Format::Json { path_only, count } => match context {
+                Some(_) if *path_only => json!({ "path": path }),
+                // The count is displayed as the line
+                Some(ctx) if *count => json!({
+                    "path": path,
+                    "count": ctx.line.parse::<usize>().unwrap_or_default(),
+                }),
+                Some(ctx) => json!({
+                    "path": path,
+                    "lno": ctx.lno,
+                    "line": ctx.line,
+                    "matches": ctx.needle.iter().map(|m| [m.start(), m.end()]).collect::<Vec<_>>(),
+                }),
+                None => json!({ "path": path }),
+            }
+            .to_string(),
+        }
+    }
+
+    fn without_path(&self) -> Option<Self> {
+        let mut format = self.clone();
+        match &mut format {
+            Format::Rich { no_path, .. } => *no_path = true,
+            _ => return None,
+        }
+        Some(format)
+    }
+
+    fn binary_file_matches(&self, path: &str) -> String {
+        let colour = match self {
+            Format::Rich { colour, .. } | Format::PathOnly { colour } => *colour,
+            Format::Fields { .. } => false,
+            Format::Json { .. } => return json!({"path": path, "binary": true}).to_string(),
+        };
+        format!("Binary file {} matches", self.format_path(path, colour))
+    }
+
+    fn file_info(&self, path: &str, info: &FileInfo) -> String {
+        match self {
+            Format::Json { .. } => json!({
+                "path": path,
+                "size": info.size,
+                "type": info.kind.as_str(),
+                "mmap": info.mapped,
+            })
+            .to_string(),
+            _ => self.format(usize::MAX, path, None),
         }
     }
 
+    fn file_events(&self) -> bool {
+        matches!(self, Format::Json { .. })
+    }
+
+    fn file_begin(&self, path: &str) -> String {
+        json!({"type": "begin", "path": path}).to_string()
+    }
+
+    fn file_end(&self, path: &str, matches: usize, bytes: usize) -> String {
+        json!({
+            "type": "end",
+            "path": path,
+            "matches": matches,
+            "bytes": bytes,
+        })
+        .to_string()
+    }
+
     fn file_separator(&self) -> String {
         self.separator("--", 203)
     }
@@ -365,7 +863,7 @@ mod tests {
             let prefix = if prefix { "[...] " } else { "" };
             let suffix = if suffix { " [...]" } else { "" };
             let needle_len = needle.end - needle.start;
-            let preambule = "/:0 ";
+            let preambule = "/:0: ";
             let formated = format!(
                 "{}{}{}{}{}{}",
                 preambule,
@@ -377,7 +875,17 @@ mod tests {
             );
             assert_eq!(
                 formated,
-                Format::Rich { colour: false }.format(
+                Format::Rich {
+                    colour: false,
+                    match_only: false,
+                    no_path: false,
+                    no_lno: false,
+                    lno_width: 0,
+                    zero_based_lno: false,
+                    show_whitespace: false,
+                    wrap: false,
+                }
+                .format(
                     width,
                     "/",
                     Some(DisplayContext::new(0, "-".repeat(len), vec![needle.into()]))
@@ -394,9 +902,9 @@ mod tests {
                 formated.len()
             );
         };
-        test(40, 80, Range { start: 4, end: 5 }, 4, 25, false, true);
-        test(40, 80, Range { start: 64, end: 65 }, 14, 15, true, false);
-        test(40, 80, Range { start: 34, end: 45 }, 7, 6, true, true);
+        test(40, 80, Range { start: 4, end: 5 }, 4, 24, false, true);
+        test(40, 80, Range { start: 64, end: 65 }, 13, 15, true, false);
+        test(40, 80, Range { start: 34, end: 45 }, 6, 6, true, true);
         test(40, 80, Range { start: 4, end: 45 }, 0, 0, false, false);
         test(40, 80, Range { start: 4, end: 75 }, 0, 0, false, false);
         test(120, 80, Range { start: 4, end: 75 }, 4, 5, false, false);
@@ -406,4 +914,149 @@ mod tests {
         test(120, 80, Range { start: 10, end: 80 }, 10, 0, false, false);
         test(120, 80, Range { start: 0, end: 70 }, 0, 10, false, false);
     }
+
+    #[test]
+    fn long_line() {
+        let format = Format::Rich {
+            colour: false,
+            match_only: false,
+            no_path: false,
+            no_lno: false,
+            lno_width: 0,
+            zero_based_lno: false,
+            show_whitespace: false,
+            wrap: false,
+        };
+        // Multi-byte chars around the needle force the margins onto char boundaries
+        let half = "é".repeat(2 << 20);
+        let line = format!("{}foo{}", half, half);
+        let needle = Range {
+            start: half.len(),
+            end: half.len() + 3,
+        };
+        let formatted = format.format(
+            41,
+            "/",
+            Some(DisplayContext::new(0, line, vec![needle.into()])),
+        );
+        assert_eq!(
+            format!("/:0: [...] {}foo{} [...]", "é".repeat(5), "é".repeat(5)),
+            formatted
+        );
+    }
+
+    #[test]
+    fn wrap() {
+        let format = |colour| Format::Rich {
+            colour,
+            match_only: false,
+            no_path: false,
+            no_lno: false,
+            lno_width: 0,
+            zero_based_lno: false,
+            show_whitespace: false,
+            wrap: true,
+        };
+        let line = "0123456789abcdefghij".to_owned();
+        let context = || {
+            Some(DisplayContext::new(
+                1,
+                line.clone(),
+                vec![Match::new(8, 12)],
+            ))
+        };
+        // The prefix takes 5 columns of the first row
+        assert_eq!(
+            "/:1: 01234\n56789abcde\nfghij",
+            format(false).format(10, "/", context())
+        );
+        // The needle crossing the row boundary is highlighted on both rows
+        let red = |what: &str| Colour::Red.paint(what).to_string();
+        let prefix = format!(
+            "{}{}{}{} ",
+            Colour::Blue.paint("/"),
+            Colour::Cyan.paint(":"),
+            Colour::Green.paint("1"),
+            Colour::Cyan.paint(":"),
+        );
+        assert_eq!(
+            format!("{}01234567{}\n{}cdefghij", prefix, red("89"), red("ab")),
+            format(true).format(15, "/", context())
+        );
+        // Rows are counted in chars
+        let context = Some(DisplayContext::new(1, "é".repeat(12), vec![]));
+        assert_eq!(
+            "/:1: ééééé\nééééééé",
+            format(false).format(10, "/", context)
+        );
+    }
+
+    #[test]
+    fn match_only_context() {
+        let format = Format::Rich {
+            colour: false,
+            match_only: true,
+            no_path: false,
+            no_lno: false,
+            lno_width: 0,
+            zero_based_lno: false,
+            show_whitespace: false,
+            wrap: false,
+        };
+        let line = "foo bar foo".to_owned();
+        let formatted = format.format(
+            80,
+            "/",
+            Some(DisplayContext::new(
+                1,
+                line.clone(),
+                vec![Match::new(0, 3), Match::new(8, 11)],
+            )),
+        );
+        assert_eq!("/:1: foo\n/:1: foo", formatted);
+        let formatted = format.format(
+            80,
+            "/",
+            Some(DisplayContext::with_lno_separator(2, line, vec![], "-")),
+        );
+        assert_eq!("/-2- foo bar foo", formatted);
+    }
+
+    #[test]
+    fn whitespace() {
+        let format = Format::Rich {
+            colour: false,
+            match_only: false,
+            no_path: false,
+            no_lno: false,
+            lno_width: 0,
+            zero_based_lno: false,
+            show_whitespace: true,
+            wrap: false,
+        };
+        let line = "\tfoo bar \t ".to_owned();
+        let formatted = format.format(
+            80,
+            "/",
+            Some(DisplayContext::new(1, line.clone(), vec![Match::new(5, 9)])),
+        );
+        assert_eq!("/:1: →foo bar·→·", formatted);
+        let format = Format::Rich {
+            colour: false,
+            match_only: true,
+            no_path: true,
+            no_lno: true,
+            lno_width: 0,
+            zero_based_lno: false,
+            show_whitespace: true,
+            wrap: false,
+        };
+        // The needle covers the trailing markers
+        let formatted = format.format(
+            80,
+            "/",
+            Some(DisplayContext::new(1, line, vec![Match::new(5, 9)])),
+        );
+        assert_eq!("bar·", formatted);
+    }
 }
@@ -1,30 +1,145 @@
 use anyhow::Error;
 use log::debug;
+use regex::Regex;
 
 use crate::utils::patterns::{Pattern, PatternSet};
 
+/// File filters follow `.gitignore` anchoring rules:
+/// a filter without a slash (`*.rs`) matches the basename in any directory,
+/// a filter with a leading or middle slash (`src/*.rs`) is relative to the search root
+/// and a filter starting with `**/` (`**/src/*.rs`) matches at any depth.
+/// A path regex, if any, must match the path relative to the search root in addition to one of the filters
 #[derive(Clone, Default)]
 pub struct Filters {
     patterns: PatternSet,
+    anchored: PatternSet,
+    case_insensitive: bool,
+    path_regex: Option<Regex>,
 }
 
 impl Filters {
-    pub fn new(strings: &[String]) -> Result<Self, Error> {
+    pub fn new(strings: &[String], case_insensitive: bool) -> Result<Self, Error> {
         let mut patterns = PatternSet::new("/");
+        let mut anchored = PatternSet::new("");
         for pattern in strings {
-            let pattern = if pattern.starts_with("**/") {
-                pattern.to_owned()
+            let pattern = if case_insensitive {
+                pattern.to_lowercase()
             } else {
-                "**/".to_owned() + pattern
+                pattern.to_owned()
             };
-            let transformed = Pattern::new(&pattern)?;
-            debug!("Transformed filter {:?} -> {:?}", pattern, transformed);
-            patterns.push(transformed, false);
+            if pattern.starts_with("**/") {
+                patterns.push(Pattern::new(&pattern)?, false);
+            } else if pattern.trim_end_matches('/').contains('/') {
+                let pattern = "/".to_owned() + pattern.trim_start_matches('/');
+                let transformed = Pattern::new(&pattern)?;
+                debug!(
+                    "Transformed anchored filter {:?} -> {:?}",
+                    pattern, transformed
+                );
+                anchored.push(transformed, false);
+            } else {
+                let pattern = "**/".to_owned() + &pattern;
+                let transformed = Pattern::new(&pattern)?;
+                debug!("Transformed filter {:?} -> {:?}", pattern, transformed);
+                patterns.push(transformed, false);
+            }
+        }
+        Ok(Filters {
+            patterns,
+            anchored,
+            case_insensitive,
+            path_regex: None,
+        })
+    }
+
+    pub fn path_regex(mut self, path_regex: Option<Regex>) -> Self {
+        self.path_regex = path_regex;
+        self
+    }
+
+    /// `root` is the search root anchored filters and the path regex are relative to
+    pub fn matches(&self, path: &str, root: &str) -> bool {
+        match &self.path_regex {
+            Some(path_regex) if !path_regex.is_match(relative(path, root)) => false,
+            _ => self.matches_patterns(path, root),
+        }
+    }
+
+    fn matches_patterns(&self, path: &str, root: &str) -> bool {
+        let (path, root) = if self.case_insensitive {
+            (path.to_lowercase(), root.to_lowercase())
+        } else {
+            (path.to_owned(), root.to_owned())
+        };
+        if self.patterns.matches(&path, false) {
+            return true;
+        }
+        let root = root.trim_end_matches('/');
+        match path.strip_prefix(root) {
+            Some(relative) if relative.starts_with('/') => self.anchored.matches(relative, false),
+            _ => false,
         }
-        Ok(Filters { patterns })
+    }
+}
+
+/// The path without the root and the separator following it, the whole path if it is not under the root
+fn relative<'a>(path: &'a str, root: &str) -> &'a str {
+    match path.strip_prefix(root.trim_end_matches('/')) {
+        Some(relative) if relative.starts_with('/') => &relative[1..],
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive() {
+        let strings = vec!["*.MD".to_string(), "Makefile".to_string()];
+        let filters = Filters::new(&strings, false).unwrap();
+        assert!(filters.matches("/a/README.MD", "/"));
+        assert!(!filters.matches("/a/readme.md", "/"));
+        assert!(!filters.matches("/a/makefile", "/"));
+        let filters = Filters::new(&strings, true).unwrap();
+        assert!(filters.matches("/a/README.MD", "/"));
+        assert!(filters.matches("/a/readme.md", "/"));
+        assert!(filters.matches("/a/makefile", "/"));
+        assert!(!filters.matches("/a/readme.txt", "/"));
+    }
+
+    #[test]
+    fn anchoring() {
+        let root = "/r";
+        let filters = Filters::new(&["*.rs".to_string()], false).unwrap();
+        assert!(filters.matches("/r/a.rs", root));
+        assert!(filters.matches("/r/src/a.rs", root));
+        assert!(filters.matches("/r/x/src/a.rs", root));
+        assert!(!filters.matches("/r/a.rsx", root));
+
+        let filters = Filters::new(&["src/*.rs".to_string()], false).unwrap();
+        assert!(filters.matches("/r/src/a.rs", root));
+        assert!(!filters.matches("/r/a.rs", root));
+        assert!(!filters.matches("/r/x/src/a.rs", root));
+        assert!(!filters.matches("/r/src/x/a.rs", root));
+        assert!(!filters.matches("/rsrc/a.rs", root));
+
+        let filters = Filters::new(&["**/src/*.rs".to_string()], false).unwrap();
+        assert!(filters.matches("/r/src/a.rs", root));
+        assert!(filters.matches("/r/x/src/a.rs", root));
+        assert!(!filters.matches("/r/a.rs", root));
+        assert!(!filters.matches("/r/src/x/a.rs", root));
     }
 
-    pub fn matches(&self, path: &str) -> bool {
-        self.patterns.matches(path, false)
+    #[test]
+    fn path_regex() {
+        let filters = Filters::new(&["*.rs".to_string()], false)
+            .unwrap()
+            .path_regex(Some(Regex::new(r"test.*\.rs$").unwrap()));
+        assert!(filters.matches("/r/tests/cli.rs", "/r"));
+        assert!(filters.matches("/r/src/a_test.rs", "/r"));
+        assert!(!filters.matches("/r/src/a.rs", "/r"));
+        // Both the regex and the filters must match
+        assert!(!filters.matches("/r/tests/cli.rs.orig", "/r"));
     }
 }
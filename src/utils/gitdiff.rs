@@ -0,0 +1,229 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use anyhow::Error;
+use log::debug;
+
+const WHOLE_FILE: Range<usize> = 1..usize::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GitDiffMode {
+    /// Changes of the tracked files only
+    Tracked,
+    /// Untracked files are searched entirely
+    All,
+}
+
+impl FromStr for GitDiffMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tracked" => Ok(GitDiffMode::Tracked),
+            "all" => Ok(GitDiffMode::All),
+            _ => anyhow::bail!("unknown git diff mode '{}'", s),
+        }
+    }
+}
+
+/// Line numbers added or changed in the working tree relative to `HEAD`, keyed by absolute path
+#[derive(Default)]
+pub struct GitDiff {
+    changes: HashMap<PathBuf, Vec<Range<usize>>>,
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| Error::new(e).context("failed to spawn 'git'"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "'git {}' failed in '{}': {}",
+            args.join(" "),
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses the `+start[,count]` part of a hunk header, a zero count stands for a removal
+fn parse_hunk(header: &str) -> Option<Range<usize>> {
+    let added = header.split(' ').find_map(|part| part.strip_prefix('+'))?;
+    let (start, count) = match added.split_once(',') {
+        Some((start, count)) => (start.parse().ok()?, count.parse().ok()?),
+        None => (added.parse().ok()?, 1),
+    };
+    Some(start..start + count)
+}
+
+/// Returns the added line ranges per path of `git diff --unified=0` output
+fn parse_diff(diff: &str) -> HashMap<String, Vec<Range<usize>>> {
+    let mut changes: HashMap<String, Vec<Range<usize>>> = HashMap::new();
+    let mut path: Option<String> = None;
+    // Added lines may look like headers, e.g. `+++ ` for an added `++ `
+    let mut in_header = false;
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            in_header = true;
+            path = None;
+        } else if line.starts_with("@@ ") {
+            in_header = false;
+            if let (Some(path), Some(range)) = (&path, parse_hunk(line)) {
+                if !range.is_empty() {
+                    changes.entry(path.clone()).or_default().push(range);
+                }
+            }
+        } else if let Some(target) = line.strip_prefix("+++ ").filter(|_| in_header) {
+            // Deleted files have no target and quoted paths are not decoded
+            path = target.strip_prefix("b/").map(str::to_owned);
+            if path.is_none() {
+                debug!("Ignoring diff target {:?}", target);
+            }
+        }
+    }
+    changes
+}
+
+/// Top-level directories of the repositories containing the paths, each one once
+fn toplevels(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut toplevels = Vec::new();
+    for path in paths {
+        let path = match path.canonicalize() {
+            Ok(path) => path,
+            // Reported by the walk
+            Err(_) => continue,
+        };
+        let dir = if path.is_dir() {
+            path.as_path()
+        } else {
+            path.parent().unwrap_or(&path)
+        };
+        let toplevel = match git(dir, &["rev-parse", "--show-toplevel"]) {
+            Ok(toplevel) => PathBuf::from(toplevel.trim_end()).canonicalize()?,
+            Err(e) => {
+                debug!("{}", e);
+                continue;
+            }
+        };
+        if !toplevels.contains(&toplevel) {
+            toplevels.push(toplevel);
+        }
+    }
+    Ok(toplevels)
+}
+
+impl GitDiff {
+    /// Collects the changes of the repositories containing the paths,
+    /// paths outside of a repository have no changes
+    pub fn new(paths: &[PathBuf], mode: GitDiffMode) -> Result<Self, Error> {
+        let mut diff = GitDiff::default();
+        for toplevel in toplevels(paths)? {
+            diff.extend(&toplevel, mode)?;
+        }
+        Ok(diff)
+    }
+
+    fn extend(&mut self, toplevel: &Path, mode: GitDiffMode) -> Result<(), Error> {
+        let args = [
+            "-c",
+            "core.quotePath=false",
+            "diff",
+            "--unified=0",
+            "--no-color",
+            "--no-ext-diff",
+            "HEAD",
+        ];
+        for (path, ranges) in parse_diff(&git(toplevel, &args)?) {
+            self.changes.insert(toplevel.join(path), ranges);
+        }
+        if mode == GitDiffMode::All {
+            let untracked = git(
+                toplevel,
+                &["ls-files", "--others", "--exclude-standard", "-z"],
+            )?;
+            for path in untracked.split('\0').filter(|path| !path.is_empty()) {
+                self.changes.insert(toplevel.join(path), vec![WHOLE_FILE]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Changed line ranges of the file, `None` if it is unchanged
+    pub fn changes(&self, path: &Path) -> Option<&[Range<usize>]> {
+        self.changes.get(path).map(Vec::as_slice)
+    }
+}
+
+/// Files tracked by git, i.e. in the index, keyed by absolute path
+#[derive(Default)]
+pub struct GitTracked {
+    files: HashSet<PathBuf>,
+}
+
+impl GitTracked {
+    /// Lists the files of the repositories containing the paths,
+    /// files outside of a repository are not tracked
+    pub fn new(paths: &[PathBuf]) -> Result<Self, Error> {
+        let mut tracked = GitTracked::default();
+        for toplevel in toplevels(paths)? {
+            let files = git(&toplevel, &["ls-files", "--cached", "-z"])?;
+            tracked.files.extend(
+                files
+                    .split('\0')
+                    .filter(|path| !path.is_empty())
+                    .map(|path| toplevel.join(path)),
+            );
+        }
+        Ok(tracked)
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.contains(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hunks() {
+        let diff = "\
+diff --git a/src/a.rs b/src/a.rs
+index 1111111..2222222 100644
+--- a/src/a.rs
++++ b/src/a.rs
+@@ -3 +3 @@ fn main() {
+-    foo
++    bar
+@@ -10,0 +11,2 @@
++baz
++++ qux
+@@ -20,2 +21,0 @@
+--- gone
+-gone
+@@ -30 +29 @@
+-x
++y
+diff --git a/old b/old
+deleted file mode 100644
+--- a/old
++++ /dev/null
+@@ -1 +0,0 @@
+-old
+";
+        let changes = parse_diff(diff);
+        assert_eq!(Some(&vec![3..4, 11..13, 29..30]), changes.get("src/a.rs"));
+        assert_eq!(1, changes.len());
+        assert_eq!("all".parse::<GitDiffMode>().unwrap(), GitDiffMode::All);
+    }
+}
@@ -1,50 +1,91 @@
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::cmp;
 use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use log::error;
+use log::{debug, error};
+use regex::Regex;
 
 use crate::utils::display::{Display, DisplayContext};
-use crate::utils::lines::LinesReader;
+use crate::utils::gitdiff::GitDiff;
+use crate::utils::lines::{Deadline, LinesReader};
+use crate::utils::mapped::LineIndex;
 use crate::utils::matcher::{Match, Matcher, MatcherOptions};
+use crate::utils::replace::{self, Replacer};
 
-pub type Grep = Arc<Box<dyn Fn(Arc<dyn LinesReader>, Matcher, Arc<dyn Display>) + Send + Sync>>;
+/// Greps the reader and returns the number of matching lines
+pub type Grep =
+    Arc<Box<dyn Fn(Arc<dyn LinesReader>, Matcher, Arc<dyn Display>) -> usize + Send + Sync>>;
 
 type OnMatch = Box<dyn Fn(DisplayContext) -> bool>;
 type OnEnd = Box<dyn Fn(usize, usize)>;
 
 fn fuzzy_grep(reader: &Arc<dyn LinesReader>, matcher: &Matcher) -> Option<()> {
-    let res = reader.map();
-    if res.is_err() {
-        // Some readers do not support map
+    if !reader.supports_map() {
         return Some(());
     }
-    res.ok()
-        .and_then(|map| matcher(map, MatcherOptions::Fuzzy).and(Some(())))
+    let map = match reader.map() {
+        Ok(map) => map,
+        Err(e) => {
+            debug!("Failed to map '{}': {}", reader.path().display(), e);
+            return Some(());
+        }
+    };
+    if map.is_empty() {
+        // Nothing to prefilter, an inverted matcher would reject it
+        return Some(());
+    }
+    matcher(map, MatcherOptions::Fuzzy).and(Some(()))
 }
 
-fn generic_grep(reader: Arc<dyn LinesReader>, matcher: Matcher, on_match: OnMatch, on_end: OnEnd) {
+fn generic_grep(
+    reader: Arc<dyn LinesReader>,
+    matcher: Matcher,
+    on_match: OnMatch,
+    on_end: OnEnd,
+) -> usize {
+    generic_grep_lines(reader, matcher, None, on_match, on_end)
+}
+
+/// Lines outside of `restrict` ranges, when given, are never matched
+fn generic_grep_lines(
+    reader: Arc<dyn LinesReader>,
+    matcher: Matcher,
+    restrict: Option<&[Range<usize>]>,
+    on_match: OnMatch,
+    on_end: OnEnd,
+) -> usize {
     if fuzzy_grep(&reader, &matcher).is_none() {
-        on_end(0, 0);
-        return;
+        // Lines were never scanned, `on_end` must not mistake this for an empty file
+        return 0;
     }
     let mut matches = 0;
     let mut total = 0;
-    match reader.lines() {
+    match reader.numbered_lines() {
         Ok(mut lines) => {
-            while let Some(line) = lines.next() {
-                total += 1;
+            while let Some((lno, line)) = lines.next() {
+                if restrict.is_some_and(|ranges| !ranges.iter().any(|range| range.contains(&lno))) {
+                    continue;
+                }
                 if let Some(needle) = matcher(line, MatcherOptions::Exact(usize::MAX)) {
                     matches += 1;
-                    if on_match(DisplayContext::new(total, line.to_string(), needle)) {
+                    if on_match(DisplayContext::new(lno, line.to_string(), needle)) {
                         break;
                     }
                 }
             }
+            total = lines.count();
         }
         Err(e) => error!("Failed to read '{}': {}", reader.path().display(), e),
     }
     on_end(total, matches);
+    matches
 }
 
 pub fn grep() -> Grep {
@@ -60,7 +101,93 @@ pub fn grep() -> Grep {
                     false
                 }),
                 Box::new(move |_, _| {}),
-            );
+            )
+        },
+    ))
+}
+
+/// Reports matches on the lines changed in the working tree only, unchanged files are not read
+pub fn grep_changed(diff: Arc<GitDiff>) -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            let path = reader.path().clone();
+            let changes = match diff.changes(&path) {
+                Some(changes) => changes,
+                None => return 0,
+            };
+            generic_grep_lines(
+                reader,
+                matcher,
+                Some(changes),
+                Box::new(move |context| {
+                    display.display(&path, Some(context));
+                    false
+                }),
+                Box::new(move |_, _| {}),
+            )
+        },
+    ))
+}
+
+/// Displays matching lines with the matches substituted, files are left untouched
+pub fn grep_replace(replacer: Arc<Replacer>) -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            let path = reader.path().clone();
+            let display = display.clone();
+            let replacer = replacer.clone();
+            generic_grep(
+                reader,
+                matcher,
+                Box::new(move |context| {
+                    let (line, needles) = replacer.replace_line(context.line());
+                    display.display(
+                        &path,
+                        Some(DisplayContext::new(context.line_number(), line, needles)),
+                    );
+                    false
+                }),
+                Box::new(move |_, _| {}),
+            )
+        },
+    ))
+}
+
+/// Rewrites the files with the matches substituted and displays the paths of the modified ones
+pub fn grep_write(replacer: Arc<Replacer>) -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            if fuzzy_grep(&reader, &matcher).is_none() {
+                return 0;
+            }
+            let path = reader.path();
+            let content = match reader.map() {
+                Ok(content) => Cow::Borrowed(content),
+                Err(_) => match fs::read_to_string(path) {
+                    Ok(content) => Cow::Owned(content),
+                    Err(e) => {
+                        error!("Failed to read '{}': {}", path.display(), e);
+                        return 0;
+                    }
+                },
+            };
+            // The mapped content is not validated
+            if let Err(e) = std::str::from_utf8(content.as_bytes()) {
+                error!("Not rewriting '{}': {}", path.display(), e);
+                return 0;
+            }
+            let (content, lines) = replacer.replace(&content, &matcher);
+            if lines == 0 {
+                return 0;
+            }
+            match replace::write(path, &content) {
+                Ok(()) => {
+                    replacer.mark_modified();
+                    display.display(path, None);
+                }
+                Err(e) => error!("Failed to write '{}': {}", path.display(), e),
+            }
+            lines
         },
     ))
 }
@@ -71,21 +198,24 @@ fn _grep_with_context(
     display: Arc<dyn Display>,
     before: usize,
     after: usize,
-) {
+    paragraph: bool,
+) -> usize {
     if fuzzy_grep(&reader, &matcher).is_none() {
-        return;
+        return 0;
     }
     let path = reader.path().clone();
     let mut lqueue: VecDeque<String> = VecDeque::with_capacity(before + 1);
-    let mut lno = 0;
-    let mut pcount: isize = 0;
+    let mut matches = 0;
+    let mut pcount = 0;
     let mut output = BTreeMap::new();
-    match reader.lines() {
+    match reader.numbered_lines() {
         Ok(mut lines) => {
-            while let Some(line) = lines.next() {
-                lno += 1;
+            while let Some((lno, line)) = lines.next() {
                 let needle = matcher(line, MatcherOptions::Exact(usize::MAX));
 
+                if paragraph && line.trim().is_empty() {
+                    pcount = 0;
+                }
                 if pcount > 0 {
                     output.entry(lno).or_insert_with(|| {
                         DisplayContext::with_lno_separator(lno, line.to_owned(), vec![], "-")
@@ -93,18 +223,15 @@ fn _grep_with_context(
                     pcount -= 1;
                 }
                 if let Some(needle) = needle {
-                    for i in 0..cmp::min(before, lqueue.len()) {
+                    matches += 1;
+                    // The queue holds the closest line at the back
+                    for (i, line) in lqueue.drain(..).rev().enumerate() {
                         output.entry(lno - i - 1).or_insert_with(|| {
-                            DisplayContext::with_lno_separator(
-                                lno - i - 1,
-                                lqueue.pop_front().unwrap(),
-                                vec![],
-                                "-",
-                            )
+                            DisplayContext::with_lno_separator(lno - i - 1, line, vec![], "-")
                         });
                     }
                     output.insert(lno, DisplayContext::new(lno, line.to_owned(), needle));
-                    pcount = after as isize;
+                    pcount = after;
                 }
                 lqueue.push_back(line.to_string());
                 if lqueue.len() == before + 1 {
@@ -122,20 +249,245 @@ fn _grep_with_context(
         }
         Err(e) => error!("Failed to read '{}': {}", reader.path().display(), e),
     }
+    matches
+}
+
+fn _grep_section(
+    reader: Arc<dyn LinesReader>,
+    matcher: Matcher,
+    display: Arc<dyn Display>,
+    header: &Regex,
+    section: &Regex,
+) -> usize {
+    if fuzzy_grep(&reader, &matcher).is_none() {
+        return 0;
+    }
+    let path = reader.path().clone();
+    let mut matches = 0;
+    // Lines before the first header are outside of any section
+    let mut inside = false;
+    match reader.numbered_lines() {
+        Ok(mut lines) => {
+            while let Some((lno, line)) = lines.next() {
+                if header.is_match(line) {
+                    inside = section.is_match(line);
+                    continue;
+                }
+                if !inside {
+                    continue;
+                }
+                if let Some(needle) = matcher(line, MatcherOptions::Exact(usize::MAX)) {
+                    matches += 1;
+                    display.display(
+                        &path,
+                        Some(DisplayContext::new(lno, line.to_owned(), needle)),
+                    );
+                }
+            }
+        }
+        Err(e) => error!("Failed to read '{}': {}", reader.path().display(), e),
+    }
+    matches
+}
+
+/// Reports matches only within the sections whose header matches `section`.
+/// A section starts at a line matching `header` and spans up to the next one,
+/// header lines themselves are never reported.
+pub fn grep_section(header: Regex, section: Regex) -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            _grep_section(reader, matcher, display, &header, &section)
+        },
+    ))
+}
+
+/// With `paragraph` the after-context also stops at the first blank line,
+/// which is not printed
+pub fn grep_with_context(before: usize, after: usize, paragraph: bool) -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            _grep_with_context(reader, matcher, display, before, after, paragraph)
+        },
+    ))
+}
+
+fn _grep_multiline(
+    reader: Arc<dyn LinesReader>,
+    matcher: Matcher,
+    display: Arc<dyn Display>,
+) -> usize {
+    let path = reader.path().clone();
+    let collected;
+    let content = match reader.map() {
+        // The mapped content is not validated, the lines decode what is not UTF-8
+        Ok(content) if std::str::from_utf8(content.as_bytes()).is_ok() => content,
+        _ => match reader.lines() {
+            Ok(mut lines) => {
+                let mut content = Vec::new();
+                while let Some(line) = lines.next() {
+                    content.push(line.to_owned());
+                }
+                collected = content.join("\n");
+                &collected
+            }
+            Err(e) => {
+                error!("Failed to read '{}': {}", path.display(), e);
+                return 0;
+            }
+        },
+    };
+    let needles = match matcher(content, MatcherOptions::Exact(usize::MAX)) {
+        Some(needles) => needles,
+        None => return 0,
+    };
+    let index = LineIndex::new(content.as_bytes());
+    // Each match is split between the lines it spans
+    let mut output: BTreeMap<usize, Vec<Match>> = BTreeMap::new();
+    for needle in &needles {
+        let (first, _) = index.position(needle.start());
+        let (last, _) = index.position(cmp::max(needle.start(), needle.end().saturating_sub(1)));
+        for lno in first..=last {
+            let line = index.line(lno, content.as_bytes());
+            let start = cmp::max(needle.start(), line.start);
+            let end = cmp::min(needle.end(), line.end);
+            if start < end || needle.start() == needle.end() {
+                output.entry(lno).or_default().push(Match::new(
+                    start - line.start,
+                    cmp::max(start, end) - line.start,
+                ));
+            }
+        }
+    }
+    // Matching lines are counted as in the other modes
+    let lines = output.len();
+    for (lno, needle) in output {
+        let line = index.line(lno, content.as_bytes());
+        display.display(
+            &path,
+            Some(DisplayContext::new(lno, content[line].to_owned(), needle)),
+        );
+    }
+    lines
+}
+
+pub fn grep_multiline() -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            _grep_multiline(reader, matcher, display)
+        },
+    ))
+}
+
+pub fn grep_passthrough() -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            if fuzzy_grep(&reader, &matcher).is_none() {
+                return 0;
+            }
+            let path = reader.path().clone();
+            let mut matches = 0;
+            // Lines are held back until the first match as files without matches are not printed
+            let mut pending = Vec::new();
+            match reader.numbered_lines() {
+                Ok(mut lines) => {
+                    while let Some((lno, line)) = lines.next() {
+                        let context = match matcher(line, MatcherOptions::Exact(usize::MAX)) {
+                            Some(needle) => {
+                                matches += 1;
+                                DisplayContext::new(lno, line.to_owned(), needle)
+                            }
+                            None => DisplayContext::with_lno_separator(
+                                lno,
+                                line.to_owned(),
+                                vec![],
+                                "-",
+                            ),
+                        };
+                        if matches == 0 {
+                            pending.push(context);
+                            continue;
+                        }
+                        for context in pending.drain(..) {
+                            display.display(&path, Some(context));
+                        }
+                        display.display(&path, Some(context));
+                    }
+                }
+                Err(e) => error!("Failed to read '{}': {}", reader.path().display(), e),
+            }
+            matches
+        },
+    ))
+}
+
+/// Counts the matching lines without displaying anything
+pub fn grep_silent() -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, _: Arc<dyn Display>| {
+            generic_grep(
+                reader,
+                matcher,
+                Box::new(move |_| false),
+                Box::new(move |_, _| {}),
+            )
+        },
+    ))
 }
 
-pub fn grep_with_context(before: usize, after: usize) -> Grep {
+/// Stops searching a file once the timeout elapses, the lines matched by then are still reported
+pub fn with_timeout(grep: Grep, timeout: Duration) -> Grep {
     Arc::new(Box::new(
         move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
-            _grep_with_context(reader, matcher, display, before, after)
+            let deadline = Instant::now() + timeout;
+            #[allow(clippy::arc_with_non_send_sync)]
+            grep(Arc::new(Deadline::new(reader, deadline)), matcher, display)
         },
     ))
 }
 
+/// Greps only the line the whole content prefilter hit lands on, which is the first matching line
+/// unless the hit spans lines. Returns `Some(None)` when the prefilter rejects the content and
+/// `None` when lines have to be scanned, e.g. the reader can't be mapped or the line doesn't match.
+fn grep_first_line(
+    reader: &Arc<dyn LinesReader>,
+    matcher: &Matcher,
+) -> Option<Option<DisplayContext<'static>>> {
+    if !reader.supports_map() {
+        return None;
+    }
+    let map = reader.map().ok().filter(|map| !map.is_empty())?;
+    let pos = match matcher(map, MatcherOptions::Fuzzy) {
+        Some(needles) => needles.first()?.end(),
+        None => return Some(None),
+    };
+    let map = map.as_bytes();
+    let start = memchr::memrchr(b'\n', &map[..pos]).map_or(0, |start| start + 1);
+    let end = memchr::memchr(b'\n', &map[pos..]).map_or(map.len(), |end| pos + end);
+    let end = if end > start && map[end - 1] == b'\r' {
+        end - 1
+    } else {
+        end
+    };
+    // Lines that are not valid UTF-8 are transformed when scanned
+    let line = std::str::from_utf8(&map[start..end]).ok()?;
+    let needle = matcher(line, MatcherOptions::Exact(usize::MAX))?;
+    let lno = memchr::memchr_iter(b'\n', &map[..start]).count() + 1;
+    Some(Some(DisplayContext::new(lno, line.to_owned(), needle)))
+}
+
+/// Reports the first matching line, mapped content is searched as a whole rather than line by line
 pub fn grep_matches_once() -> Grep {
     Arc::new(Box::new(
         move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
             let path = reader.path().clone();
+            match grep_first_line(&reader, &matcher) {
+                Some(Some(context)) => {
+                    display.display(&path, Some(context));
+                    return 1;
+                }
+                Some(None) => return 0,
+                None => {}
+            }
             let display = display.clone();
             generic_grep(
                 reader,
@@ -145,7 +497,25 @@ pub fn grep_matches_once() -> Grep {
                     true
                 }),
                 Box::new(move |_, _| {}),
-            );
+            )
+        },
+    ))
+}
+
+pub fn grep_binary() -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            let path = reader.path().clone();
+            let display = display.clone();
+            generic_grep(
+                reader,
+                matcher,
+                Box::new(move |_| {
+                    display.binary_file_matches(&path);
+                    true
+                }),
+                Box::new(move |_, _| {}),
+            )
         },
     ))
 }
@@ -160,39 +530,642 @@ pub fn grep_matches_all_lines() -> Grep {
                 matcher,
                 Box::new(move |_| false),
                 Box::new(move |total, matches| {
-                    if matches == total && total != 0 {
+                    if matches == total {
                         display.display(&path, None);
                     }
                 }),
+            )
+        },
+    ))
+}
+
+/// Displays the files without any match. Unlike `grep_matches_all_lines` with an inverted
+/// matcher, the matches are counted as is, so that with `-v` the files without any
+/// non-matching line are displayed
+pub fn grep_files_zero_match() -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            let path = reader.path().clone();
+            // Not in `on_end`, which is skipped when the prefilter rejects the file
+            let matches = generic_grep(
+                reader,
+                matcher,
+                // A single match is enough to skip the file
+                Box::new(move |_| true),
+                Box::new(move |_, _| {}),
             );
+            if matches == 0 {
+                display.display(&path, None);
+            }
+            matches
         },
     ))
 }
 
-pub fn grep_count() -> Grep {
+pub fn grep_count_per_line() -> Grep {
     Arc::new(Box::new(
         move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
             let path = reader.path().clone();
             let display = display.clone();
             generic_grep(
+                reader,
+                matcher,
+                Box::new(move |context| {
+                    let matches = context.matches().len().to_string();
+                    let matches_len = matches.len();
+                    display.display(
+                        &path,
+                        Some(DisplayContext::new(
+                            context.line_number(),
+                            matches,
+                            vec![Match::new(0, matches_len)],
+                        )),
+                    );
+                    false
+                }),
+                Box::new(move |_, _| {}),
+            )
+        },
+    ))
+}
+
+/// Displays the count as the line, highlighted as a match
+fn display_count(display: &Arc<dyn Display>, path: &Path, count: usize) {
+    let count = count.to_string();
+    let count_len = count.len();
+    display.display(
+        path,
+        Some(DisplayContext::new(
+            0,
+            count,
+            vec![Match::new(0, count_len)],
+        )),
+    );
+}
+
+/// Counts the matching lines, e.g. 1 for `foo foo`.
+/// With `include_zero` files without matches are reported too.
+pub fn grep_count(include_zero: bool) -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            let path = reader.path().clone();
+            // Not in `on_end`, which is skipped when the prefilter rejects the file
+            let matches = generic_grep(
                 reader,
                 matcher,
                 Box::new(move |_| false),
-                Box::new(move |_, matches| {
-                    if matches > 0 {
-                        let matches = matches.to_string();
-                        let matches_len = matches.len();
-                        display.display(
-                            &path,
-                            Some(DisplayContext::new(
-                                0,
-                                matches,
-                                vec![Match::new(0, matches_len)],
-                            )),
-                        );
+                Box::new(move |_, _| {}),
+            );
+            if matches > 0 || include_zero {
+                display_count(&display, &path, matches);
+            }
+            matches
+        },
+    ))
+}
+
+/// Counts the matches, e.g. 2 for `foo foo`. The count is returned, so that the walk stats
+/// add up to the total. With `include_zero` files without matches are reported too.
+pub fn grep_count_matches(include_zero: bool) -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            let path = reader.path().clone();
+            let count = Rc::new(Cell::new(0));
+            generic_grep(
+                reader,
+                matcher,
+                {
+                    let count = count.clone();
+                    Box::new(move |context| {
+                        count.set(count.get() + context.matches().len());
+                        false
+                    })
+                },
+                Box::new(move |_, _| {}),
+            );
+            let count = count.get();
+            if count > 0 || include_zero {
+                display_count(&display, &path, count);
+            }
+            count
+        },
+    ))
+}
+
+/// Displays the percentage of matching lines, e.g. `33%` for 1 line out of 3.
+/// Empty files have no matching lines, they are at 0%.
+pub fn grep_count_percent(include_zero: bool) -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, matcher: Matcher, display: Arc<dyn Display>| {
+            let path = reader.path().clone();
+            let total = Rc::new(Cell::new(0));
+            let matches = generic_grep(reader, matcher, Box::new(move |_| false), {
+                let total = total.clone();
+                Box::new(move |lines, _| total.set(lines))
+            });
+            // `on_end` is skipped when the prefilter rejects the file, then nothing matches
+            let percent = (matches * 100).checked_div(total.get()).unwrap_or(0);
+            if matches > 0 || include_zero {
+                let percent = format!("{}%", percent);
+                let percent_len = percent.len();
+                display.display(
+                    &path,
+                    Some(DisplayContext::new(
+                        0,
+                        percent,
+                        vec![Match::new(0, percent_len)],
+                    )),
+                );
+            }
+            matches
+        },
+    ))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CountUnit {
+    /// Runs of non-whitespace characters
+    Words,
+    /// Unicode scalar values, line terminators excluded
+    Chars,
+}
+
+/// Counts the words or characters of the file like `wc` instead of grepping it,
+/// the matcher is not applied. The count is returned, so that the walk stats add up to the total.
+pub fn grep_units(unit: CountUnit) -> Grep {
+    Arc::new(Box::new(
+        move |reader: Arc<dyn LinesReader>, _: Matcher, display: Arc<dyn Display>| {
+            let mut total = 0;
+            match reader.lines() {
+                Ok(mut lines) => {
+                    while let Some(line) = lines.next() {
+                        total += match unit {
+                            CountUnit::Words => line.split_whitespace().count(),
+                            CountUnit::Chars => line.chars().count(),
+                        };
                     }
-                }),
+                }
+                Err(e) => error!("Failed to read '{}': {}", reader.path().display(), e),
+            }
+            let count = total.to_string();
+            let count_len = count.len();
+            display.display(
+                reader.path(),
+                Some(DisplayContext::new(
+                    0,
+                    count,
+                    vec![Match::new(0, count_len)],
+                )),
             );
+            total
         },
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::Mutex,
+    };
+
+    use ansi_term::Colour;
+
+    use super::*;
+    use crate::utils::display::{DisplayTerminal, Format};
+    use crate::utils::lines::StringReader;
+    use crate::utils::mapped::Mapped;
+    use crate::utils::matcher::MatchLimit;
+    use crate::utils::writer::Writer;
+
+    #[derive(Default)]
+    struct Capture {
+        lines: Mutex<Vec<String>>,
+    }
+
+    impl Writer for Capture {
+        fn write(&self, content: &str) {
+            self.lines.lock().unwrap().push(content.to_owned());
+        }
+    }
+
+    fn matcher(regexp: &str) -> Matcher {
+        let regexp = Regex::new(regexp).unwrap();
+        Arc::new(Box::new(move |line: &str, options| match options {
+            MatcherOptions::Fuzzy => regexp
+                .shortest_match(line)
+                .map(|pos| vec![Match::new(0, pos)]),
+            MatcherOptions::Exact(max) => {
+                let matches: Vec<_> = regexp
+                    .find_iter(line)
+                    .take(max)
+                    .map(|m| Match::new(m.start(), m.end()))
+                    .collect();
+                if matches.is_empty() {
+                    None
+                } else {
+                    Some(matches)
+                }
+            }
+        }))
+    }
+
+    fn run(grep: Grep, format: Format, content: &str, regexp: &str) -> Vec<String> {
+        run_matcher(grep, format, content, matcher(regexp))
+    }
+
+    fn run_matcher(grep: Grep, format: Format, content: &str, matcher: Matcher) -> Vec<String> {
+        let writer = Arc::new(Capture::default());
+        let display = DisplayTerminal::new(
+            usize::MAX,
+            format,
+            Arc::new(Box::new(|path: &Path| path.to_str().unwrap().to_owned())),
+            writer.clone(),
+        );
+        let reader = StringReader::new(PathBuf::from("f"), content);
+        grep(Arc::new(reader), matcher, Arc::new(display));
+        let lines = writer.lines.lock().unwrap();
+        lines.clone()
+    }
+
+    fn rich(match_only: bool, no_lno: bool) -> Format {
+        Format::Rich {
+            colour: false,
+            match_only,
+            no_path: false,
+            no_lno,
+            lno_width: 0,
+            zero_based_lno: false,
+            show_whitespace: false,
+            wrap: false,
+        }
+    }
+
+    #[test]
+    fn count_per_line() {
+        let content = "a b a\nb\na a a\n";
+        assert_eq!(
+            vec!["f:1: 2", "f:3: 3"],
+            run(grep_count_per_line(), rich(true, false), content, "a"),
+        );
+        // `-c` counts matching lines per file rather than matches per line
+        assert_eq!(
+            vec!["f: 2"],
+            run(grep_count(false), rich(false, true), content, "a"),
+        );
+        assert!(run(grep_count(false), rich(false, true), content, "z").is_empty());
+        assert_eq!(
+            vec!["f: 0"],
+            run(grep_count(true), rich(false, true), content, "z"),
+        );
+        assert!(run(grep_count_per_line(), rich(true, false), content, "z").is_empty());
+    }
+
+    #[test]
+    fn units() {
+        let content = "foo  bar\n\n\tbaz é\n";
+        assert_eq!(
+            vec!["f: 4"],
+            run(
+                grep_units(CountUnit::Words),
+                rich(false, true),
+                content,
+                "z"
+            ),
+        );
+        assert_eq!(
+            vec!["f: 14"],
+            run(
+                grep_units(CountUnit::Chars),
+                rich(false, true),
+                content,
+                "z"
+            ),
+        );
+        assert_eq!(
+            vec!["f: 0"],
+            run(grep_units(CountUnit::Words), rich(false, true), "", "z"),
+        );
+    }
+
+    #[test]
+    fn count_matches() {
+        let content = "foo foo\nbar\nfoo\n";
+        assert_eq!(
+            vec!["f: 2"],
+            run(grep_count(false), rich(false, true), content, "foo"),
+        );
+        assert_eq!(
+            vec!["f: 3"],
+            run(grep_count_matches(false), rich(false, true), content, "foo"),
+        );
+        assert!(run(grep_count_matches(false), rich(false, true), content, "baz").is_empty());
+        assert_eq!(
+            vec!["f: 0"],
+            run(grep_count_matches(true), rich(false, true), content, "baz"),
+        );
+    }
+
+    #[test]
+    fn count_percent() {
+        let percent = |content, include_zero| {
+            run(
+                grep_count_percent(include_zero),
+                rich(false, true),
+                content,
+                "foo",
+            )
+        };
+        assert_eq!(vec!["f: 33%"], percent("foo\nbar\nbaz\n", false));
+        assert_eq!(vec!["f: 50%"], percent("foo foo\nbar\n", false));
+        assert_eq!(vec!["f: 100%"], percent("foo\n", false));
+        assert!(percent("bar\n", false).is_empty());
+        assert_eq!(vec!["f: 0%"], percent("bar\n", true));
+        assert_eq!(vec!["f: 0%"], percent("", true));
+    }
+
+    #[test]
+    fn matches_once() {
+        let content = "x\r\nfoo a\r\nbar a\r\n";
+        assert_eq!(
+            vec!["f:2: foo a"],
+            run(grep_matches_once(), rich(false, false), content, "a"),
+        );
+        // The prefilter hit spans the first lines, the lines are scanned then
+        let content = "xa\nb\nab\na b\n";
+        assert_eq!(
+            vec!["f:4: a b"],
+            run(grep_matches_once(), rich(false, false), content, r"a\sb"),
+        );
+        assert!(run(grep_matches_once(), rich(false, false), content, "z").is_empty());
+    }
+
+    #[test]
+    fn context_edges() {
+        let content = "a\nb\nc\nd\na\n";
+        // Match on the first line with before-context
+        assert_eq!(
+            vec!["f:1: a", "..", "f-3- c", "f-4- d", "f:5: a"],
+            run(
+                grep_with_context(2, 0, false),
+                rich(false, false),
+                content,
+                "a"
+            ),
+        );
+        // Before-context lines are emitted in order
+        assert_eq!(
+            vec!["f-2- b", "f-3- c", "f:4: d"],
+            run(
+                grep_with_context(2, 0, false),
+                rich(false, false),
+                content,
+                "d"
+            ),
+        );
+        // Match on the last line with after-context, no trailing separator
+        assert_eq!(
+            vec!["f:1: a", "f-2- b", "f-3- c", "f-4- d", "f:5: a"],
+            run(
+                grep_with_context(0, 3, false),
+                rich(false, false),
+                content,
+                "a"
+            ),
+        );
+        assert_eq!(
+            vec!["f:1: a", "f-2- b", "..", "f:5: a"],
+            run(
+                grep_with_context(0, 1, false),
+                rich(false, false),
+                content,
+                "a"
+            ),
+        );
+        // Adjacent matches with overlapping windows
+        assert_eq!(
+            vec!["f-1- a", "f:2: b", "f:3: c", "f-4- d"],
+            run(
+                grep_with_context(1, 1, false),
+                rich(false, false),
+                content,
+                "b|c"
+            ),
+        );
+    }
+
+    #[test]
+    fn context_paragraph() {
+        let content = "a\nb\nc\n  \nd\na\n";
+        // Whitespace-only lines are blank too
+        assert_eq!(
+            vec!["f:1: a", "f-2- b", "f-3- c", "..", "f:6: a"],
+            run(
+                grep_with_context(0, usize::MAX, true),
+                rich(false, false),
+                content,
+                "a"
+            ),
+        );
+        // The count still caps the paragraph
+        assert_eq!(
+            vec!["f:1: a", "f-2- b", "..", "f:6: a"],
+            run(
+                grep_with_context(0, 1, true),
+                rich(false, false),
+                content,
+                "a"
+            ),
+        );
+    }
+
+    #[test]
+    fn context_merge() {
+        let content = "x\na\nx\nx\nx\na\nx\nx\n";
+        // After-context of the first match overlaps before-context of the second
+        assert_eq!(
+            vec!["f-1- x", "f:2: a", "f-3- x", "f-4- x", "f-5- x", "f:6: a", "f-7- x", "f-8- x"],
+            run(
+                grep_with_context(2, 2, false),
+                rich(false, false),
+                content,
+                "a"
+            ),
+        );
+        // Windows touch each other
+        assert_eq!(
+            vec!["f-1- x", "f:2: a", "f-3- x", "f-4- x", "f-5- x", "f:6: a", "f-7- x", "f-8- x"],
+            run(
+                grep_with_context(1, 2, false),
+                rich(false, false),
+                content,
+                "a"
+            ),
+        );
+        // Windows are one line apart
+        assert_eq!(
+            vec!["f-1- x", "f:2: a", "f-3- x", "..", "f-5- x", "f:6: a", "f-7- x"],
+            run(
+                grep_with_context(1, 1, false),
+                rich(false, false),
+                content,
+                "a"
+            ),
+        );
+        // A match within the after-context extends it
+        assert_eq!(
+            vec!["f:1: a", "f:2: a", "f-3- x", "..", "f:5: a", "f-6- x"],
+            run(
+                grep_with_context(0, 1, false),
+                rich(false, false),
+                "a\na\nx\nx\na\nx\n",
+                "a"
+            ),
+        );
+    }
+
+    #[test]
+    fn context_highlight() {
+        let format = Format::Rich {
+            colour: true,
+            match_only: false,
+            no_path: false,
+            no_lno: false,
+            lno_width: 0,
+            zero_based_lno: false,
+            show_whitespace: false,
+            wrap: false,
+        };
+        let prefix = |lno: &str, sep: &str| {
+            format!(
+                "{}{}{}{} ",
+                Colour::Blue.paint("f"),
+                Colour::Cyan.paint(sep),
+                Colour::Green.paint(lno),
+                Colour::Cyan.paint(sep)
+            )
+        };
+        // Context lines are neither highlighted nor separated with `:` even if they match,
+        // here once the limit is reached
+        assert_eq!(
+            vec![
+                format!("{}x", prefix("1", "-")),
+                format!(
+                    "{}{}{}b{}",
+                    prefix("2", ":"),
+                    // Empty truncation markers
+                    Colour::Purple.paint(""),
+                    Colour::Red.paint("a"),
+                    Colour::Purple.paint("")
+                ),
+                format!("{}xa", prefix("3", "-")),
+            ],
+            run_matcher(
+                grep_with_context(1, 1, false),
+                format,
+                "x\nab\nxa\n",
+                MatchLimit::new(1).limit(matcher("a"))
+            ),
+        );
+    }
+
+    #[test]
+    fn binary() {
+        let content = "\x01a\x02\na\n";
+        assert_eq!(
+            vec!["Binary file f matches"],
+            run(grep_binary(), rich(false, false), content, "a"),
+        );
+        assert!(run(grep_binary(), rich(false, false), content, "z").is_empty());
+    }
+
+    #[test]
+    fn section() {
+        let content = "port = 1\n[server]\nport = 2\n[client]\nport = 3\n  [server]  \nport = 4\n";
+        let header = || Regex::new(r"^\s*\[.*\]\s*$").unwrap();
+        assert_eq!(
+            vec!["f:3: port = 2", "f:7: port = 4"],
+            run(
+                grep_section(header(), Regex::new("server").unwrap()),
+                rich(false, false),
+                content,
+                "port"
+            ),
+        );
+        // Header lines are never reported, even when they match
+        assert!(run(
+            grep_section(header(), Regex::new("server").unwrap()),
+            rich(false, false),
+            content,
+            "server"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn passthrough() {
+        assert_eq!(
+            vec!["f-1- x", "f:2: a", "f-3- x"],
+            run(grep_passthrough(), rich(false, false), "x\na\nx\n", "a"),
+        );
+        assert!(run(grep_passthrough(), rich(false, false), "x\nx\n", "a").is_empty());
+    }
+
+    #[test]
+    fn multiline() {
+        let content = "x\nfoo\nbar\nbaz\nx\n";
+        assert_eq!(
+            vec!["f:2: o", "f:3: b"],
+            run(grep_multiline(), rich(true, false), content, "o\nb"),
+        );
+        assert_eq!(
+            vec!["f:2: foo", "f:3: bar", "f:4: ba"],
+            run(grep_multiline(), rich(true, false), content, "foo\nbar\nba"),
+        );
+        assert_eq!(
+            vec!["f:3: r", "f:4: b"],
+            run(
+                grep_multiline(),
+                rich(true, false),
+                "x\r\nfoo\nbar\r\nbaz",
+                "r\r\nb"
+            ),
+        );
+        assert_eq!(
+            vec!["f:2: foo"],
+            run(grep_multiline(), rich(false, false), content, "foo"),
+        );
+        assert!(run(grep_multiline(), rich(false, false), content, "o\nx").is_empty());
+    }
+
+    #[test]
+    fn multiline_latin1() {
+        let path = std::env::temp_dir().join(format!("tgrep-multiline-{}", std::process::id()));
+        fs::write(&path, b"foo\n\xa9bar\n").unwrap();
+        let run = |format: Format, pattern: &str| {
+            let writer = Arc::new(Capture::default());
+            let display = DisplayTerminal::new(
+                usize::MAX,
+                format,
+                Arc::new(Box::new(|_: &Path| "f".to_owned())),
+                writer.clone(),
+            );
+            let reader = Mapped::new(&path, 9, false).unwrap();
+            #[allow(clippy::arc_with_non_send_sync)]
+            let count = grep_multiline()(Arc::new(reader), matcher(pattern), Arc::new(display));
+            let lines = writer.lines.lock().unwrap();
+            (count, lines.clone())
+        };
+        assert_eq!(
+            (1, vec!["f:2: ©bar".to_owned()]),
+            run(rich(false, false), "bar")
+        );
+        assert_eq!(
+            (1, vec!["f:2: bar".to_owned()]),
+            run(rich(true, false), "bar")
+        );
+        // Matching lines are counted, not matches
+        assert_eq!(2, run(rich(false, false), "o|a").0);
+        fs::remove_file(&path).unwrap();
+    }
+}
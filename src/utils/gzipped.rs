@@ -0,0 +1,120 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::utils::lines::{LineIterator, Lines, LinesReader};
+use crate::utils::mapped::Mapped;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// As much as `content_inspector` looks at
+const INSPECTED_LEN: u64 = 1024;
+
+pub struct Gzipped {
+    path: PathBuf,
+    /// Decompressed instead of the file when the file is already mapped
+    mapped: Option<Mapped>,
+}
+
+impl Gzipped {
+    pub fn new(path: PathBuf) -> Self {
+        Gzipped { path, mapped: None }
+    }
+
+    pub fn from_mapped(path: PathBuf, mapped: Mapped) -> Self {
+        Gzipped {
+            path,
+            mapped: Some(mapped),
+        }
+    }
+
+    pub fn has_extension(path: &Path) -> bool {
+        path.extension() == Some("gz".as_ref())
+    }
+
+    /// Told by the extension or by the magic bytes of the content
+    pub fn is_gzipped(path: &Path, content: &[u8]) -> bool {
+        Self::has_extension(path) || content.starts_with(&GZIP_MAGIC)
+    }
+
+    fn decoder(&self) -> io::Result<MultiGzDecoder<Box<dyn Read>>> {
+        let reader: Box<dyn Read> = match &self.mapped {
+            Some(mapped) => Box::new(io::Cursor::new(mapped.bytes())),
+            None => Box::new(io::BufReader::new(File::open(&self.path)?)),
+        };
+        Ok(MultiGzDecoder::new(reader))
+    }
+
+    /// Inspects the beginning of the decompressed content as plain files are
+    pub fn is_binary(&self) -> io::Result<bool> {
+        let mut head = Vec::new();
+        self.decoder()?.take(INSPECTED_LEN).read_to_end(&mut head)?;
+        Ok(content_inspector::inspect(&head).is_binary())
+    }
+}
+
+impl LinesReader for Gzipped {
+    fn lines(&self) -> anyhow::Result<Box<LineIterator>> {
+        Ok(Box::new(Lines::new(
+            io::BufReader::new(self.decoder()?),
+            self.path.clone(),
+        )))
+    }
+
+    fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io::Write, process};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    #[test]
+    fn gzipped_lines() {
+        let dir = env::temp_dir().join(format!("tgrep-gzipped-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(b"foo\r\nbar\nbaz").unwrap();
+        encoder.finish().unwrap();
+        let plain = dir.join("plain.txt");
+        fs::write(&plain, "foo\n").unwrap();
+
+        let mapped = |path: &Path| {
+            Mapped::new(path, fs::metadata(path).unwrap().len() as usize, false).unwrap()
+        };
+        assert!(Gzipped::is_gzipped(&path, &mapped(&path)));
+        assert!(!Gzipped::is_gzipped(&plain, &mapped(&plain)));
+        let collect = |reader: Gzipped| {
+            assert!(!reader.is_binary().unwrap());
+            let mut lines = reader.lines().unwrap();
+            let mut collected = Vec::new();
+            while let Some(line) = lines.next() {
+                collected.push(line.to_owned());
+            }
+            collected
+        };
+        assert_eq!(
+            vec!["foo", "bar", "baz"],
+            collect(Gzipped::new(path.clone()))
+        );
+        let reader = Gzipped::from_mapped(path.clone(), mapped(&path));
+        assert_eq!(vec!["foo", "bar", "baz"], collect(reader));
+
+        let binary = dir.join("binary.gz");
+        let mut encoder = GzEncoder::new(File::create(&binary).unwrap(), Compression::default());
+        encoder.write_all(b"foo\0bar\n").unwrap();
+        encoder.finish().unwrap();
+        assert!(Gzipped::new(binary).is_binary().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,6 +2,8 @@ use std::{
     fs::File,
     io::{self, BufRead},
     path::PathBuf,
+    sync::Arc,
+    time::Instant,
 };
 
 use log::{debug, warn};
@@ -11,12 +13,45 @@ use streaming_iterator::StreamingIterator;
 pub type LineIterator = dyn StreamingIterator<Item = str>;
 
 pub trait LinesReader {
+    /// Whether `map` is available, streaming readers skip the fuzzy pre-pass
+    fn supports_map(&self) -> bool {
+        false
+    }
+
     fn map(&self) -> anyhow::Result<&str> {
         anyhow::bail!("not supported");
     }
 
     fn lines(&self) -> anyhow::Result<Box<LineIterator>>;
     fn path(&self) -> &PathBuf;
+
+    fn numbered_lines(&self) -> anyhow::Result<NumberedLines> {
+        Ok(NumberedLines::new(self.lines()?))
+    }
+}
+
+/// Yields lines along with their 1-based numbers
+pub struct NumberedLines {
+    lines: Box<LineIterator>,
+    lno: usize,
+}
+
+impl NumberedLines {
+    pub fn new(lines: Box<LineIterator>) -> Self {
+        NumberedLines { lines, lno: 0 }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(usize, &str)> {
+        let line = self.lines.next()?;
+        self.lno += 1;
+        Some((self.lno, line))
+    }
+
+    /// Number of lines yielded so far
+    pub fn count(&self) -> usize {
+        self.lno
+    }
 }
 
 impl LinesReader for PathBuf {
@@ -95,6 +130,45 @@ where
     }
 }
 
+/// In-memory reader to exercise grep modes without touching disk
+#[cfg(test)]
+pub struct StringReader {
+    path: PathBuf,
+    content: String,
+}
+
+#[cfg(test)]
+impl StringReader {
+    pub fn new(path: PathBuf, content: &str) -> Self {
+        StringReader {
+            path,
+            content: content.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl LinesReader for StringReader {
+    fn supports_map(&self) -> bool {
+        true
+    }
+
+    fn map(&self) -> anyhow::Result<&str> {
+        Ok(&self.content)
+    }
+
+    fn lines(&self) -> anyhow::Result<Box<LineIterator>> {
+        Ok(Box::new(Lines::new(
+            io::Cursor::new(self.content.clone()),
+            self.path.clone(),
+        )))
+    }
+
+    fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
 #[derive(Clone, PartialOrd, PartialEq, Ord, Eq)]
 pub struct Zero {
     path: PathBuf,
@@ -107,6 +181,10 @@ impl Zero {
 }
 
 impl LinesReader for Zero {
+    fn supports_map(&self) -> bool {
+        true
+    }
+
     fn map(&self) -> anyhow::Result<&str> {
         Ok("")
     }
@@ -129,3 +207,136 @@ impl StreamingIterator for Zero {
         None
     }
 }
+
+/// Lines are checked against the deadline in batches, reading the clock per line is not free
+const DEADLINE_CHECK_LINES: usize = 1024;
+
+/// Stops yielding lines past the deadline. The clock is read every `DEADLINE_CHECK_LINES` lines
+/// only, and a single slow line or a search of the whole content is never interrupted.
+pub struct Deadline {
+    reader: Arc<dyn LinesReader>,
+    deadline: Instant,
+}
+
+impl Deadline {
+    pub fn new(reader: Arc<dyn LinesReader>, deadline: Instant) -> Self {
+        Deadline { reader, deadline }
+    }
+}
+
+impl LinesReader for Deadline {
+    fn supports_map(&self) -> bool {
+        self.reader.supports_map()
+    }
+
+    fn map(&self) -> anyhow::Result<&str> {
+        self.reader.map()
+    }
+
+    fn lines(&self) -> anyhow::Result<Box<LineIterator>> {
+        Ok(Box::new(DeadlineLines {
+            lines: self.reader.lines()?,
+            path: self.reader.path().clone(),
+            deadline: self.deadline,
+            scanned: 0,
+            expired: false,
+        }))
+    }
+
+    fn path(&self) -> &PathBuf {
+        self.reader.path()
+    }
+}
+
+struct DeadlineLines {
+    lines: Box<LineIterator>,
+    path: PathBuf,
+    deadline: Instant,
+    scanned: usize,
+    expired: bool,
+}
+
+impl DeadlineLines {
+    fn expire(&mut self) -> bool {
+        if !self.expired {
+            self.scanned += 1;
+            if self.scanned.is_multiple_of(DEADLINE_CHECK_LINES) && Instant::now() >= self.deadline
+            {
+                warn!(
+                    "Timed out searching '{}', {} lines searched",
+                    self.path.display(),
+                    self.scanned - 1
+                );
+                self.expired = true;
+            }
+        }
+        self.expired
+    }
+}
+
+impl StreamingIterator for DeadlineLines {
+    type Item = str;
+
+    fn advance(&mut self) {
+        if !self.expire() {
+            self.lines.advance();
+        }
+    }
+
+    fn get(&self) -> Option<&Self::Item> {
+        if self.expired {
+            None
+        } else {
+            self.lines.get()
+        }
+    }
+
+    // Mapped lines are yielded by `next` only
+    fn next(&mut self) -> Option<&Self::Item> {
+        if self.expire() {
+            None
+        } else {
+            self.lines.next()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbered_lines() {
+        let reader = StringReader::new(PathBuf::from("f"), "foo\r\n\nbar");
+        assert_eq!("foo\r\n\nbar", reader.map().unwrap());
+        let mut lines = reader.numbered_lines().unwrap();
+        assert_eq!(Some((1, "foo")), lines.next());
+        assert_eq!(Some((2, "")), lines.next());
+        assert_eq!(Some((3, "bar")), lines.next());
+        assert_eq!(None, lines.next());
+        assert_eq!(3, lines.count());
+        let mut lines = Zero::new(PathBuf::from("f")).numbered_lines().unwrap();
+        assert_eq!(None, lines.next());
+        assert_eq!(0, lines.count());
+    }
+
+    #[test]
+    fn deadline() {
+        let content = "foo\n".repeat(3 * DEADLINE_CHECK_LINES);
+        let reader = Arc::new(StringReader::new(PathBuf::from("f"), &content));
+        let count = |reader: Deadline| {
+            let mut lines = reader.numbered_lines().unwrap();
+            while lines.next().is_some() {}
+            lines.count()
+        };
+        assert_eq!(
+            DEADLINE_CHECK_LINES - 1,
+            count(Deadline::new(reader.clone(), Instant::now()))
+        );
+        let later = Instant::now() + std::time::Duration::from_secs(3600);
+        assert_eq!(
+            3 * DEADLINE_CHECK_LINES,
+            count(Deadline::new(reader, later))
+        );
+    }
+}
@@ -5,6 +5,7 @@ use std::{
     str,
 };
 
+use encoding_rs::{Encoding, UTF_16LE};
 use log::debug;
 use memchr::memchr;
 use memmap2::{Mmap, MmapOptions};
@@ -19,19 +20,61 @@ struct MappedInner {
 
 pub struct Mapped {
     mapped: Rc<MappedInner>,
+    /// Decodes the lines that are not valid UTF-8
+    fallback: Option<&'static Encoding>,
 }
 
 impl Mapped {
-    pub fn new(path: &Path, len: usize) -> anyhow::Result<Self> {
+    /// With `populate` the pages are read ahead instead of being faulted in one at a time
+    pub fn new(path: &Path, len: usize, populate: bool) -> anyhow::Result<Self> {
         let file = fs::File::open(path)?;
-        let mmap = unsafe { MmapOptions::new().len(len).map(&file)? };
+        let mut options = MmapOptions::new();
+        options.len(len);
+        if populate {
+            options.populate();
+        }
+        let mmap = unsafe { options.map(&file)? };
         Ok(Mapped {
             mapped: Rc::new(MappedInner {
                 path: path.to_owned(),
                 mmap,
             }),
+            fallback: None,
         })
     }
+
+    /// Lines that are not valid UTF-8 are decoded with the encoding instead of byte by byte.
+    /// The content is validated once, a file in the fallback encoding can't be mapped
+    /// as the whole content search would see the undecoded bytes.
+    pub fn fallback(mut self, encoding: &'static Encoding) -> Self {
+        if str::from_utf8(&self).is_err() {
+            self.fallback = Some(encoding);
+        }
+        self
+    }
+}
+
+impl Mapped {
+    /// Reads the content as UTF-16, the byte order mark is skipped if present
+    pub fn into_utf16(self, encoding: &'static Encoding) -> MappedUtf16 {
+        MappedUtf16 {
+            mapped: self.mapped,
+            encoding,
+        }
+    }
+
+    /// The raw content, e.g. to be decompressed
+    pub fn bytes(&self) -> MappedBytes {
+        MappedBytes(self.mapped.clone())
+    }
+}
+
+pub struct MappedBytes(Rc<MappedInner>);
+
+impl AsRef<[u8]> for MappedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0.mmap
+    }
 }
 
 impl ops::Deref for Mapped {
@@ -39,17 +82,24 @@ impl ops::Deref for Mapped {
 
     #[inline(always)]
     fn deref(&self) -> &[u8] {
-        &*self.mapped.mmap
+        &self.mapped.mmap
     }
 }
 
 impl LinesReader for Mapped {
+    fn supports_map(&self) -> bool {
+        self.fallback.is_none()
+    }
+
     fn map(&self) -> anyhow::Result<&str> {
-        Ok(unsafe { str::from_utf8_unchecked(&*self) })
+        Ok(unsafe { str::from_utf8_unchecked(self) })
     }
 
     fn lines(&self) -> anyhow::Result<Box<LineIterator>> {
-        Ok(Box::new(MappedLines::new(self.mapped.clone())?))
+        Ok(Box::new(MappedLines::new(
+            self.mapped.clone(),
+            self.fallback,
+        )?))
     }
 
     fn path(&self) -> &PathBuf {
@@ -57,17 +107,58 @@ impl LinesReader for Mapped {
     }
 }
 
+/// Maps byte offsets of a buffer to line numbers
+pub struct LineIndex {
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(buf: &[u8]) -> Self {
+        let mut starts = vec![0];
+        starts.extend(memchr::memchr_iter(b'\n', buf).map(|pos| pos + 1));
+        LineIndex {
+            starts,
+            len: buf.len(),
+        }
+    }
+
+    /// 1-based line number and column of the byte at the offset
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let line = match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        (line + 1, offset - self.starts[line] + 1)
+    }
+
+    /// Byte range of the 1-based line excluding the line terminator
+    pub fn line(&self, lno: usize, buf: &[u8]) -> ops::Range<usize> {
+        let start = self.starts[lno - 1];
+        let mut end = match self.starts.get(lno) {
+            Some(next) => next - 1,
+            None => self.len,
+        };
+        if end > start && buf[end - 1] == b'\r' {
+            end -= 1;
+        }
+        ops::Range { start, end }
+    }
+}
+
 struct MappedLines {
     mapped: Rc<MappedInner>,
+    fallback: Option<&'static Encoding>,
     line: ops::Range<usize>,
     pos: usize,
     buf: String,
 }
 
 impl MappedLines {
-    fn new(mapped: Rc<MappedInner>) -> anyhow::Result<Self> {
+    fn new(mapped: Rc<MappedInner>, fallback: Option<&'static Encoding>) -> anyhow::Result<Self> {
         Ok(MappedLines {
             mapped,
+            fallback,
             line: ops::Range { start: 0, end: 0 },
             pos: 0,
             buf: String::new(),
@@ -92,7 +183,7 @@ impl StreamingIterator for MappedLines {
         if self.pos < self.mapped.mmap.len() && mmap[self.pos] == b'\r' {
             self.pos += 1;
         }
-        if (1..mmap.len()).contains(&self.line.end) && mmap[self.line.end] == b'\r' {
+        if self.line.end > self.line.start && mmap[self.line.end - 1] == b'\r' {
             self.line.end -= 1;
         }
     }
@@ -110,7 +201,10 @@ impl StreamingIterator for MappedLines {
         match str::from_utf8(line) {
             Ok(line) => Some(line),
             Err(e) => {
-                self.buf = line.iter().map(|&c| c as char).collect();
+                self.buf = match self.fallback {
+                    Some(encoding) => encoding.decode_without_bom_handling(line).0.into_owned(),
+                    None => line.iter().map(|&c| c as char).collect(),
+                };
                 debug!(
                     "UTF-8 decoding failure of '{}' at [{};{}], transformed to '{}'",
                     self.mapped.path.display(),
@@ -123,3 +217,209 @@ impl StreamingIterator for MappedLines {
         }
     }
 }
+
+/// A mapped UTF-16 file, lines are split at 2-byte terminators and decoded one by one,
+/// hence the match offsets are relative to the decoded lines
+pub struct MappedUtf16 {
+    mapped: Rc<MappedInner>,
+    encoding: &'static Encoding,
+}
+
+impl LinesReader for MappedUtf16 {
+    fn lines(&self) -> anyhow::Result<Box<LineIterator>> {
+        let mapped = Mapped {
+            mapped: self.mapped.clone(),
+            fallback: None,
+        };
+        Ok(Box::new(Utf16Lines::new(mapped, self.encoding)))
+    }
+
+    fn path(&self) -> &PathBuf {
+        &self.mapped.path
+    }
+}
+
+struct Utf16Lines<B> {
+    buf: B,
+    encoding: &'static Encoding,
+    pos: usize,
+    line: String,
+    end: bool,
+}
+
+impl<B> Utf16Lines<B>
+where
+    B: ops::Deref<Target = [u8]>,
+{
+    fn new(buf: B, encoding: &'static Encoding) -> Self {
+        let bom = if encoding == UTF_16LE {
+            [0xff, 0xfe]
+        } else {
+            [0xfe, 0xff]
+        };
+        let pos = if buf.starts_with(&bom) { 2 } else { 0 };
+        Utf16Lines {
+            buf,
+            encoding,
+            pos,
+            line: String::new(),
+            end: false,
+        }
+    }
+
+    /// Offset of the next `\n` code unit, a `0x0a` byte is only a terminator at an even
+    /// distance from the line start and with a zero byte on the proper side
+    fn find_terminator(&self, start: usize) -> Option<usize> {
+        let terminator: [u8; 2] = if self.encoding == UTF_16LE {
+            [b'\n', 0]
+        } else {
+            [0, b'\n']
+        };
+        let mut from = start;
+        while let Some(pos) = memchr(b'\n', &self.buf[from..]) {
+            let pos = from + pos;
+            // The big-endian unit starts with the zero byte
+            let unit = if self.encoding == UTF_16LE {
+                Some(pos)
+            } else {
+                pos.checked_sub(1)
+            };
+            let unit = unit.filter(|unit| {
+                *unit >= start
+                    && (unit - start).is_multiple_of(2)
+                    && self.buf.get(*unit..unit + 2) == Some(&terminator[..])
+            });
+            if unit.is_some() {
+                return unit;
+            }
+            from = pos + 1;
+        }
+        None
+    }
+}
+
+impl<B> StreamingIterator for Utf16Lines<B>
+where
+    B: ops::Deref<Target = [u8]>,
+{
+    type Item = str;
+
+    fn advance(&mut self) {
+        let start = self.pos;
+        if start >= self.buf.len() {
+            self.end = true;
+            return;
+        }
+        let end = match self.find_terminator(start) {
+            Some(end) => {
+                self.pos = end + 2;
+                end
+            }
+            None => {
+                self.pos = self.buf.len();
+                self.buf.len()
+            }
+        };
+        let (line, _) = self
+            .encoding
+            .decode_without_bom_handling(&self.buf[start..end]);
+        self.line = line.into_owned();
+        if self.line.ends_with('\r') {
+            self.line.pop();
+        }
+    }
+
+    fn get(&self) -> Option<&Self::Item> {
+        if self.end {
+            None
+        } else {
+            Some(&self.line)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::UTF_16BE;
+
+    use super::*;
+
+    fn utf16_lines(buf: Vec<u8>, encoding: &'static Encoding) -> Vec<String> {
+        let mut lines = Utf16Lines::new(buf, encoding);
+        let mut collected = Vec::new();
+        while let Some(line) = lines.next() {
+            collected.push(line.to_owned());
+        }
+        collected
+    }
+
+    #[test]
+    fn utf16() {
+        let encode = |content: &str, le: bool| -> Vec<u8> {
+            let mut buf = if le {
+                vec![0xff, 0xfe]
+            } else {
+                vec![0xfe, 0xff]
+            };
+            for unit in content.encode_utf16() {
+                buf.extend(if le {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                });
+            }
+            buf
+        };
+        // `Ċ` is U+010A, its bytes contain 0x0a without being a terminator
+        let content = "foo\r\nĊbar\n\nbaz€";
+        assert_eq!(
+            vec!["foo", "Ċbar", "", "baz€"],
+            utf16_lines(encode(content, true), UTF_16LE)
+        );
+        assert_eq!(
+            vec!["foo", "Ċbar", "", "baz€"],
+            utf16_lines(encode(content, false), UTF_16BE)
+        );
+        assert_eq!(vec!["a"], utf16_lines(encode("a\n", true), UTF_16LE));
+        // A stray `0x0a` leading byte without the byte order mark
+        assert_eq!(vec!["\u{a00}"], utf16_lines(vec![b'\n', 0], UTF_16BE));
+    }
+
+    #[test]
+    fn fallback() {
+        let path = std::env::temp_dir().join(format!("tgrep-fallback-{}", std::process::id()));
+        fs::write(&path, b"caf\xc3\xa9 \xe2\x82\xac\ncaf\xe9 \x80\n").unwrap();
+        let lines = |mapped: Mapped| {
+            let mut lines = mapped.lines().unwrap();
+            let mut collected = Vec::new();
+            while let Some(line) = lines.next() {
+                collected.push(line.to_owned());
+            }
+            collected
+        };
+        let mapped = || Mapped::new(&path, 17, false).unwrap();
+        assert_eq!(
+            vec!["café €", "café €"],
+            lines(mapped().fallback(encoding_rs::WINDOWS_1252))
+        );
+        assert_eq!(vec!["café €", "caf\u{e9} \u{80}"], lines(mapped()));
+        assert!(!mapped().fallback(encoding_rs::WINDOWS_1252).supports_map());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn line_index() {
+        let buf = b"foo\r\nbar\n\nbaz";
+        let index = LineIndex::new(buf);
+        assert_eq!((1, 1), index.position(0));
+        assert_eq!((1, 3), index.position(2));
+        assert_eq!((1, 5), index.position(4));
+        assert_eq!((2, 1), index.position(5));
+        assert_eq!((3, 1), index.position(9));
+        assert_eq!((4, 3), index.position(12));
+        assert_eq!(0..3, index.line(1, buf));
+        assert_eq!(5..8, index.line(2, buf));
+        assert_eq!(9..9, index.line(3, buf));
+        assert_eq!(10..13, index.line(4, buf));
+    }
+}
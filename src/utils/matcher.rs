@@ -1,4 +1,13 @@
-use std::sync::Arc;
+use std::{
+    cmp,
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use log::debug;
 
 #[derive(Clone)]
 pub struct Match {
@@ -44,3 +53,129 @@ impl From<Match> for std::ops::Range<usize> {
         }
     }
 }
+
+/// Caps the number of matches across all files sharing the limit
+#[derive(Clone)]
+pub struct MatchLimit {
+    max: usize,
+    count: Arc<AtomicUsize>,
+}
+
+impl MatchLimit {
+    pub fn new(max: usize) -> Self {
+        MatchLimit {
+            max,
+            count: Default::default(),
+        }
+    }
+
+    pub fn reached(&self) -> bool {
+        self.count.load(Ordering::Relaxed) >= self.max
+    }
+
+    /// Takes up to `wanted` matches, returns how many were granted
+    fn acquire(&self, wanted: usize) -> usize {
+        let taken = self.count.fetch_add(wanted, Ordering::Relaxed);
+        cmp::min(wanted, self.max.saturating_sub(taken))
+    }
+
+    pub fn limit(&self, matcher: Matcher) -> Matcher {
+        let limit = self.clone();
+        Arc::new(Box::new(move |line: &str, options| {
+            if limit.reached() {
+                return None;
+            }
+            match options {
+                MatcherOptions::Fuzzy => matcher(line, options),
+                MatcherOptions::Exact(_) => {
+                    let mut needles = matcher(line, options)?;
+                    // A line selected without any match, e.g. with -v, counts as one
+                    let granted = limit.acquire(cmp::max(needles.len(), 1));
+                    if granted == 0 {
+                        return None;
+                    }
+                    needles.truncate(granted);
+                    Some(needles)
+                }
+            }
+        }))
+    }
+}
+
+/// Matches each distinct line only once across all files sharing the set.
+/// Files are searched concurrently, so which of them reports a shared line first is not defined.
+#[derive(Clone, Default)]
+pub struct Unique {
+    seen: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Unique {
+    pub fn filter(&self, matcher: Matcher) -> Matcher {
+        let seen = self.seen.clone();
+        Arc::new(Box::new(move |line: &str, options| match options {
+            MatcherOptions::Fuzzy => matcher(line, options),
+            MatcherOptions::Exact(_) => {
+                matcher(line, options).filter(|_| seen.lock().unwrap().insert(line.to_owned()))
+            }
+        }))
+    }
+}
+
+/// Lines longer than `max` bytes never match, unlike truncating them for display
+pub fn skip_long_lines(matcher: Matcher, max: usize) -> Matcher {
+    Arc::new(Box::new(move |line: &str, options| match options {
+        MatcherOptions::Fuzzy => matcher(line, options),
+        MatcherOptions::Exact(_) if line.len() > max => {
+            debug!("Skipping a line of {} bytes", line.len());
+            None
+        }
+        MatcherOptions::Exact(_) => matcher(line, options),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_limit() {
+        let limit = MatchLimit::new(2);
+        let matcher = limit.limit(Arc::new(Box::new(|line: &str, _| {
+            line.find('a').map(|pos| vec![Match::new(pos, pos + 1)])
+        })));
+        assert!(matcher("a", MatcherOptions::Fuzzy).is_some());
+        assert!(matcher("b", MatcherOptions::Exact(usize::MAX)).is_none());
+        assert!(matcher("a", MatcherOptions::Exact(usize::MAX)).is_some());
+        assert!(!limit.reached());
+        assert!(matcher("a", MatcherOptions::Exact(usize::MAX)).is_some());
+        assert!(limit.reached());
+        assert!(matcher("a", MatcherOptions::Fuzzy).is_none());
+        assert!(matcher("a", MatcherOptions::Exact(usize::MAX)).is_none());
+    }
+
+    #[test]
+    fn unique() {
+        let matcher = Unique::default().filter(Arc::new(Box::new(|line: &str, _| {
+            line.find('a').map(|pos| vec![Match::new(pos, pos + 1)])
+        })));
+        assert!(matcher("ab", MatcherOptions::Exact(usize::MAX)).is_some());
+        assert!(matcher("ab", MatcherOptions::Fuzzy).is_some());
+        assert!(matcher("ab", MatcherOptions::Exact(usize::MAX)).is_none());
+        assert!(matcher("b", MatcherOptions::Exact(usize::MAX)).is_none());
+        assert!(matcher("ba", MatcherOptions::Exact(usize::MAX)).is_some());
+    }
+
+    #[test]
+    fn long_lines() {
+        let matcher = skip_long_lines(
+            Arc::new(Box::new(|line: &str, _| {
+                line.find('a').map(|pos| vec![Match::new(pos, pos + 1)])
+            })),
+            3,
+        );
+        assert!(matcher("aaa", MatcherOptions::Exact(usize::MAX)).is_some());
+        assert!(matcher("aaaa", MatcherOptions::Exact(usize::MAX)).is_none());
+        // The whole content is prefiltered
+        assert!(matcher("aaaa", MatcherOptions::Fuzzy).is_some());
+    }
+}
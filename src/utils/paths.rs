@@ -0,0 +1,52 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Expresses `path` relative to `base`, both being absolute and normalized
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+    while let (Some(p), Some(b)) = (path_components.peek(), base_components.peek()) {
+        if p != b {
+            break;
+        }
+        path_components.next();
+        base_components.next();
+    }
+    let mut relative: PathBuf = base_components.map(|_| Component::ParentDir).collect();
+    relative.extend(path_components);
+    if relative.as_os_str().is_empty() {
+        relative.push(Component::CurDir);
+    }
+    relative
+}
+
+/// Replaces the `from` path separators of a displayed path, e.g. `\\` with `/` on Windows
+pub fn replace_separator(path: &str, from: char, to: char) -> String {
+    path.replace(from, to.encode_utf8(&mut [0; 4]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative() {
+        let test = |path: &str, base: &str, expected: &str| {
+            assert_eq!(
+                PathBuf::from(expected),
+                relative_to(Path::new(path), Path::new(base))
+            );
+        };
+        test("/a/b/c", "/a/b", "c");
+        test("/a/b/c", "/a/d", "../b/c");
+        test("/a/b/c", "/x/y", "../../a/b/c");
+        test("/a/b", "/a/b/c", "..");
+        test("/a/b", "/a/b", ".");
+        test("/a/b", "/", "a/b");
+    }
+
+    #[test]
+    fn separator() {
+        assert_eq!("a/b/c.txt", replace_separator(r"a\b\c.txt", '\\', '/'));
+        assert_eq!("a:b", replace_separator("a/b", '/', ':'));
+    }
+}
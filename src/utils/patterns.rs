@@ -75,11 +75,19 @@ fn find_in_string(haystack: &str, needle: &str) -> Option<usize> {
 // 5.4 Other consecutive asterisks are considered regular asterisks and
 //     will match according to the previous rules.
 
+// `*` and `?` must not cross directory boundaries, see 4.1 and 4.2 above
+const GLOB_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
 #[derive(PartialEq)]
 enum PatternType {
     Any,
     Exact(String),
     Prefix(String),
+    Children(String),
     Suffix(String),
     StarSuffix(String),
     PrefixStar(String),
@@ -96,6 +104,7 @@ impl fmt::Debug for PatternType {
             Any => formatter.write_str("Any"),
             Exact(pattern) => formatter.write_fmt(format_args!("Exact({:?})", pattern)),
             Prefix(pattern) => formatter.write_fmt(format_args!("Prefix({:?})", pattern)),
+            Children(pattern) => formatter.write_fmt(format_args!("Children({:?})", pattern)),
             Suffix(pattern) => formatter.write_fmt(format_args!("Suffix({:?})", pattern)),
             StarSuffix(pattern) => formatter.write_fmt(format_args!("StarSuffix({:?})", pattern)),
             PrefixStar(pattern) => formatter.write_fmt(format_args!("PrefixStar({:?})", pattern)),
@@ -123,13 +132,18 @@ impl Pattern {
         } else if let Some(capture) = Self::re(r"**(/[:]*)", pattern) {
             // `**/foo`
             PatternType::Suffix(capture)
-        } else if let Some(capture) = Self::re(r"**/([:]*)\*", pattern) {
+        } else if let Some(capture) =
+            Self::re(r"**/([:]*)\*", pattern).filter(|capture| !capture.contains('/'))
+        {
             // `**/foo*`
             PatternType::PrefixStar(capture)
+        } else if let Some(capture) = Self::re(r"(/(?:[:]*/)?)\*", pattern) {
+            // `/foo/*`
+            PatternType::Children(capture)
         } else if let Some(capture) = Self::re(r"(/[:]*)\*", pattern) {
             // `/foo*`
             PatternType::Prefix(capture)
-        } else if let Some((first, second)) = Self::re2(r"**/([:]*/)**(/[:]*)", pattern) {
+        } else if let Some((first, second)) = Self::re2(r"**(/[:]*/)**(/[:]*)", pattern) {
             // `**/foo/**/bar`
             PatternType::DStarTextDStarText((first, second))
         } else if let Some(capture) = Self::re(r"(/[:]*)", pattern) {
@@ -179,6 +193,12 @@ impl Pattern {
                     && path.is_char_boundary(pattern.len())
                     && &path[..pattern.len()] == pattern
             }
+            PatternType::Children(pattern) => {
+                path.len() > pattern.len()
+                    && path.is_char_boundary(pattern.len())
+                    && &path[..pattern.len()] == pattern
+                    && !path[pattern.len()..].contains('/')
+            }
             PatternType::Suffix(pattern) => {
                 path.len() >= pattern.len()
                     && path.is_char_boundary(path.len() - pattern.len())
@@ -201,20 +221,17 @@ impl Pattern {
                     && &path[path.len() - pattern.len()..] == pattern
             }
             PatternType::DStarTextDStarText((first, second)) => {
-                if path.len() > first.len() + second.len() {
-                    if let Some(pos) = find_in_string(path, first) {
-                        let path = &path[pos + first.len()..];
-                        path.len() > second.len()
-                            && path.is_char_boundary(path.len() - second.len())
-                            && &path[path.len() - second.len()..] == second
-                    } else {
-                        false
-                    }
+                // `/**/` matches zero or more directories, the slash ending `first` is kept
+                if let Some(pos) = find_in_string(path, first) {
+                    let path = &path[pos + first.len() - 1..];
+                    path.len() >= second.len()
+                        && path.is_char_boundary(path.len() - second.len())
+                        && &path[path.len() - second.len()..] == second
                 } else {
                     false
                 }
             }
-            PatternType::Glob(pattern) => pattern.matches(path),
+            PatternType::Glob(pattern) => pattern.matches_with(path, GLOB_OPTIONS),
         };
         trace!(
             "Testing {:?} against {:?}: {}",
@@ -232,11 +249,18 @@ impl fmt::Debug for Pattern {
     }
 }
 
+#[derive(Clone, PartialEq)]
+struct Rule {
+    pattern: Pattern,
+    dir_only: bool,
+    negated: bool,
+}
+
+/// Patterns sharing a root, the last matching pattern decides whether a path is excluded
 #[derive(Clone, PartialEq, Default)]
 pub(crate) struct PatternSet {
     root: Arc<String>,
-    dir_only: Vec<Pattern>,
-    all: Vec<Pattern>,
+    rules: Vec<Rule>,
 }
 
 impl PatternSet {
@@ -248,37 +272,80 @@ impl PatternSet {
     }
 
     pub(crate) fn push(&mut self, pattern: Pattern, dir_only: bool) {
-        if dir_only {
-            self.dir_only.push(pattern);
-        } else {
-            self.all.push(pattern);
-        }
+        self.rules.push(Rule {
+            pattern,
+            dir_only,
+            negated: false,
+        });
+    }
+
+    /// Re-includes the paths matched by `pattern` that are excluded by the previous patterns
+    pub(crate) fn push_negated(&mut self, pattern: Pattern, dir_only: bool) {
+        self.rules.push(Rule {
+            pattern,
+            dir_only,
+            negated: true,
+        });
     }
 
     pub(crate) fn matches(&self, path: &str, is_dir: bool) -> bool {
+        self.excludes(path, is_dir) == Some(true)
+    }
+
+    /// `None` if no pattern matches, otherwise whether the last matching pattern excludes the path
+    fn excludes(&self, path: &str, is_dir: bool) -> Option<bool> {
         // NOTE: this is faster than `path.trim_start_matches(&*self.root)`
         let truncated = if path.len() >= self.root.len() && path[..self.root.len()] == *self.root {
             &path[self.root.len()..]
         } else {
             path
         };
-        if is_dir {
-            let matches = self
-                .dir_only
-                .iter()
-                .any(|pattern| pattern.matches(truncated));
-            if matches {
-                return true;
-            }
-        }
-        self.all.iter().any(|pattern| pattern.matches(truncated))
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.pattern.matches(truncated))
+            .map(|rule| !rule.negated)
     }
 }
 
+/// Outcome of matching a path against `Patterns`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatternMatch {
+    /// The last matching pattern is a `!` one
+    Whitelisted,
+    Blacklisted,
+    Unmatched,
+}
+
+/// `.gitignore` patterns, the sets are ordered from the deepest directory
+/// so that a nested `.gitignore` overrides the ones of its parents
 #[derive(Clone, Default)]
 pub struct Patterns {
-    whitelist: Vec<PatternSet>,
-    blacklist: Vec<PatternSet>,
+    sets: Vec<PatternSet>,
+}
+
+/// Turns the escaped characters into literals, glob metacharacters become single-character
+/// classes as the glob crate has no escape character, e.g. `\*` -> `[*]`
+fn unescape(pattern: &str) -> String {
+    let mut unescaped = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(c @ ('*' | '?' | '[')) => {
+                unescaped.push('[');
+                unescaped.push(c);
+                unescaped.push(']');
+            }
+            Some(c) => unescaped.push(c),
+            // A trailing backslash is kept as is
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
 }
 
 impl Patterns {
@@ -299,17 +366,14 @@ impl Patterns {
             return None;
         }
         let pattern = pattern.replace("\\ ", " ");
-        let whitelist = pattern.starts_with('!');
-        let pattern = if whitelist {
+        let negated = pattern.starts_with('!');
+        let pattern = if negated {
             &pattern[1..]
         } else {
             pattern.as_str()
         };
-        let pattern = if pattern.starts_with("\\#") || pattern.starts_with("\\!") {
-            pattern.strip_prefix('\\').unwrap()
-        } else {
-            pattern
-        };
+        let pattern = unescape(pattern);
+        let pattern = pattern.as_str();
         // `./.git` == `/.git`
         let pattern = if pattern.starts_with("./") {
             pattern.strip_prefix('.').unwrap()
@@ -317,13 +381,9 @@ impl Patterns {
             pattern
         };
         let root_only = pattern.starts_with('/')
-            || (pattern.contains('/')
-                && !pattern.ends_with('/')
-                && !pattern.starts_with("**/")
-                && !pattern.contains("/**/"));
-        let dir_only = pattern.ends_with('/') || pattern.ends_with("/*");
+            || (pattern.contains('/') && !pattern.ends_with('/') && !pattern.starts_with("**/"));
+        let dir_only = pattern.ends_with('/');
         let pattern = pattern.trim_end_matches('/');
-        let pattern = pattern.trim_end_matches("/*");
         let pattern = if root_only {
             "/".to_owned() + pattern.trim_start_matches('/')
         } else if !pattern.starts_with("**/") {
@@ -333,63 +393,73 @@ impl Patterns {
         };
         let transformed = Pattern::new(&pattern);
         debug!(
-            "Transformed pattern {:?} -> {:?} -> {:?} (root:{:?}, dir:{}, whitelist:{})",
-            orig, pattern, transformed, root, dir_only, whitelist,
+            "Transformed pattern {:?} -> {:?} -> {:?} (root:{:?}, dir:{}, negated:{})",
+            orig, pattern, transformed, root, dir_only, negated,
         );
-        Some((transformed, whitelist, dir_only))
+        Some((transformed, negated, dir_only))
     }
 
+    /// Patterns relative to `root`, the directory a `.gitignore` with these lines would be in.
+    /// Paths outside of `root` are matched as a whole
     pub fn new(root: &str, strings: &[String]) -> Self {
-        let mut whitelist = PatternSet::new(root);
-        let mut blacklist = PatternSet::new(root);
+        Self::build(root, strings, true)
+    }
+
+    /// Same as `Patterns::new` with the lines of `content`, e.g. a `.gitignore` not read from disk
+    pub fn from_lines(root: &str, content: &str) -> Self {
+        let strings: Vec<_> = content.lines().map(str::to_owned).collect();
+        Self::new(root, &strings)
+    }
+
+    /// Drops the `!` patterns, so that re-included paths stay excluded
+    pub fn without_negations(root: &str, strings: &[String]) -> Self {
+        Self::build(root, strings, false)
+    }
+
+    fn build(root: &str, strings: &[String], negations: bool) -> Self {
+        let mut set = PatternSet::new(root);
         for pattern in strings {
             match Self::parse(root, pattern) {
-                Some((Ok(pattern), is_whitelisted, dir_only)) => {
-                    if is_whitelisted {
-                        whitelist.push(pattern, dir_only)
-                    } else {
-                        blacklist.push(pattern, dir_only)
-                    }
+                Some((Ok(_), true, _)) if !negations => {
+                    debug!("Dropping negated pattern '{}'", pattern)
                 }
+                Some((Ok(pattern), true, dir_only)) => set.push_negated(pattern, dir_only),
+                Some((Ok(pattern), false, dir_only)) => set.push(pattern, dir_only),
                 Some((Err(e), _, _)) => error!("Failed to compile pattern '{}': {}", pattern, e),
                 None => {}
             }
         }
-        let mut patterns: Patterns = Default::default();
-        patterns.whitelist.push(whitelist);
-        patterns.whitelist.dedup();
-        patterns.blacklist.push(blacklist);
-        patterns.blacklist.dedup();
-        patterns
+        Patterns { sets: vec![set] }
     }
 
+    /// Appends the patterns of a parent directory, the current ones take precedence
     pub fn extend(&mut self, other: &Patterns) {
-        self.whitelist.extend_from_slice(&other.whitelist);
-        self.whitelist.dedup();
-        self.blacklist.extend_from_slice(&other.blacklist);
-        self.blacklist.dedup();
+        self.sets.extend_from_slice(&other.sets);
+        self.sets.dedup();
     }
 
-    pub fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
-        if self
-            .whitelist
-            .iter()
-            .any(|pattern| pattern.matches(path, is_dir))
-        {
-            return false;
+    /// Whether the path is whitelisted or blacklisted by the pattern deciding for it, if any
+    pub fn classify(&self, path: &str, is_dir: bool) -> PatternMatch {
+        match self.sets.iter().find_map(|set| set.excludes(path, is_dir)) {
+            Some(true) => PatternMatch::Blacklisted,
+            Some(false) => PatternMatch::Whitelisted,
+            None => PatternMatch::Unmatched,
         }
-        self.blacklist
-            .iter()
-            .any(|pattern| pattern.matches(path, is_dir))
+    }
+
+    /// The last matching pattern of the deepest `.gitignore` wins, as in git.
+    /// A path under an excluded directory can't be re-included, the walk doesn't enter it
+    pub fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        self.classify(path, is_dir) == PatternMatch::Blacklisted
     }
 }
 
 pub trait ToPatterns {
-    fn to_patterns(&self) -> anyhow::Result<Patterns>;
+    fn to_patterns(&self, negations: bool) -> anyhow::Result<Patterns>;
 }
 
 impl ToPatterns for PathBuf {
-    fn to_patterns(&self) -> anyhow::Result<Patterns> {
+    fn to_patterns(&self, negations: bool) -> anyhow::Result<Patterns> {
         let mut contents = self.lines()?;
         let mut lines = Vec::new();
         while let Some(line) = contents.next() {
@@ -398,21 +468,23 @@ impl ToPatterns for PathBuf {
         let root = self.as_path().parent().unwrap();
         let root = root.canonicalize().unwrap();
         let root = root.to_str().unwrap();
-        Ok(Patterns::new(root, &lines))
+        Ok(if negations {
+            Patterns::new(root, &lines)
+        } else {
+            Patterns::without_negations(root, &lines)
+        })
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use env_logger;
 
     fn init() {
         let _ = env_logger::builder()
-            .is_test(match std::env::var("RUST_LOG_CAPTURE") {
-                Ok(val) if val == "n" => false,
-                _ => true,
-            })
+            .is_test(!matches!(std::env::var("RUST_LOG_CAPTURE"), Ok(val) if val == "n"))
             .try_init();
     }
 
@@ -455,15 +527,20 @@ mod tests {
             "**/tata",
             "titi/**/titi",
             "tutu/**",
+            // Escapes
+            r"foo\[bar\]",
+            r"a\*b",
+            r"what\?",
+            r"back\\slash",
         ]
         .iter()
         .map(|e| e.to_string())
         .collect::<Vec<String>>();
-        for root in vec!["/", "/r/"] {
+        for root in ["/", "/r/"] {
             let patterns = Patterns::new(root, &strings);
             let mkpath = |path| root.to_owned() + path;
 
-            for is_dir in vec![true, false] {
+            for is_dir in [true, false] {
                 // 0.
                 assert_eq!(true, patterns.is_excluded(&mkpath(" "), is_dir));
                 assert_eq!(true, patterns.is_excluded(&mkpath("bim"), is_dir));
@@ -485,7 +562,8 @@ mod tests {
                 assert_eq!(false, patterns.is_excluded(&mkpath("buz/bar/baz"), is_dir));
 
                 assert_eq!(is_dir, patterns.is_excluded(&mkpath("baz/buz"), is_dir));
-                assert_eq!(is_dir, patterns.is_excluded(&mkpath("baz/buzz"), is_dir));
+                assert_eq!(false, patterns.is_excluded(&mkpath("baz/buzz"), is_dir));
+                assert_eq!(true, patterns.is_excluded(&mkpath("baz/buzz/x"), is_dir));
 
                 assert_eq!(is_dir, patterns.is_excluded(&mkpath("baz/qux"), is_dir));
                 assert_eq!(is_dir, patterns.is_excluded(&mkpath("baz/qux"), is_dir));
@@ -510,7 +588,8 @@ mod tests {
                     true,
                     patterns.is_excluded(&mkpath("titi/baz/boz/titi"), is_dir)
                 );
-                assert_eq!(false, patterns.is_excluded(&mkpath("titi/titi"), is_dir));
+                // `/**/` also matches zero directories
+                assert_eq!(true, patterns.is_excluded(&mkpath("titi/titi"), is_dir));
                 assert_eq!(
                     false,
                     patterns.is_excluded(&mkpath("titi/tutu/baz/boz"), is_dir)
@@ -519,7 +598,144 @@ mod tests {
                     true,
                     patterns.is_excluded(&mkpath("tutu/baz/boz/titi"), is_dir)
                 );
+
+                // Escapes
+                assert_eq!(true, patterns.is_excluded(&mkpath("foo[bar]"), is_dir));
+                assert_eq!(false, patterns.is_excluded(&mkpath("foob"), is_dir));
+                assert_eq!(true, patterns.is_excluded(&mkpath("x/a*b"), is_dir));
+                assert_eq!(false, patterns.is_excluded(&mkpath("axb"), is_dir));
+                assert_eq!(true, patterns.is_excluded(&mkpath("what?"), is_dir));
+                assert_eq!(false, patterns.is_excluded(&mkpath("whats"), is_dir));
+                assert_eq!(true, patterns.is_excluded(&mkpath("back\\slash"), is_dir));
+            }
+        }
+    }
+
+    #[test]
+    fn double_star() {
+        init();
+
+        let strings = ["abc/**", "a/**/b", "**/c/**/d", "e/**/f/"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>();
+        for root in ["/", "/r/"] {
+            let patterns = Patterns::new(root, &strings);
+            let excluded =
+                |path: &str, is_dir| patterns.is_excluded(&(root.to_owned() + path), is_dir);
+
+            for is_dir in [true, false] {
+                // 5.2 Everything inside, not the directory itself
+                assert_eq!(true, excluded("abc/x", is_dir));
+                assert_eq!(true, excluded("abc/x/y", is_dir));
+                assert_eq!(false, excluded("abc", is_dir));
+                assert_eq!(false, excluded("abcd/x", is_dir));
+                assert_eq!(false, excluded("x/abc/y", is_dir));
+
+                // 5.3 Zero or more directories, relative to the root with a middle slash
+                assert_eq!(true, excluded("a/b", is_dir));
+                assert_eq!(true, excluded("a/x/b", is_dir));
+                assert_eq!(true, excluded("a/x/y/b", is_dir));
+                assert_eq!(false, excluded("x/a/b", is_dir));
+                assert_eq!(false, excluded("a/bb", is_dir));
+                assert_eq!(false, excluded("ab", is_dir));
+
+                // 5.1 and 5.3 combined, at any level
+                assert_eq!(true, excluded("c/d", is_dir));
+                assert_eq!(true, excluded("x/c/y/d", is_dir));
+                assert_eq!(false, excluded("xc/d", is_dir));
+                assert_eq!(false, excluded("c/xd", is_dir));
+
+                // 2.4 Directories only
+                assert_eq!(is_dir, excluded("e/f", is_dir));
+                assert_eq!(is_dir, excluded("e/x/f", is_dir));
             }
         }
     }
+
+    #[test]
+    fn classify() {
+        init();
+
+        let strings = vec!["*.log".to_string(), "!keep.log".to_string()];
+        let patterns = Patterns::new("/", &strings);
+        assert_eq!(
+            PatternMatch::Blacklisted,
+            patterns.classify("/a.log", false)
+        );
+        assert_eq!(
+            PatternMatch::Whitelisted,
+            patterns.classify("/keep.log", false)
+        );
+        assert_eq!(PatternMatch::Unmatched, patterns.classify("/a.txt", false));
+    }
+
+    #[test]
+    fn root() {
+        init();
+
+        let content = "/build\nlogs/*.log\n# comment\n*.tmp\n";
+        let patterns = Patterns::from_lines("/repo", content);
+        assert_eq!(true, patterns.is_excluded("/repo/build", true));
+        assert_eq!(false, patterns.is_excluded("/repo/src/build", true));
+        assert_eq!(true, patterns.is_excluded("/repo/logs/a.log", false));
+        assert_eq!(true, patterns.is_excluded("/repo/src/a.tmp", false));
+        // Anchored patterns follow the root
+        let patterns = Patterns::from_lines("/repo/src", content);
+        assert_eq!(false, patterns.is_excluded("/repo/build", true));
+        assert_eq!(true, patterns.is_excluded("/repo/src/build", true));
+        assert_eq!(false, patterns.is_excluded("/repo/logs/a.log", false));
+        assert_eq!(true, patterns.is_excluded("/repo/src/a.tmp", false));
+    }
+
+    #[test]
+    fn without_negations() {
+        init();
+
+        let strings = vec!["*.log".to_string(), "!keep.log".to_string()];
+        let patterns = Patterns::new("/", &strings);
+        assert_eq!(true, patterns.is_excluded("/a.log", false));
+        assert_eq!(false, patterns.is_excluded("/keep.log", false));
+        let patterns = Patterns::without_negations("/", &strings);
+        assert_eq!(true, patterns.is_excluded("/a.log", false));
+        assert_eq!(true, patterns.is_excluded("/keep.log", false));
+    }
+
+    #[test]
+    fn negation_order() {
+        init();
+
+        let patterns = |strings: &[&str]| {
+            let strings = strings.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+            Patterns::new("/r", &strings)
+        };
+
+        // Exclude everything except directory `foo/bar`
+        let patterns_ = patterns(&["/*", "!/foo", "/foo/*", "!/foo/bar"]);
+        assert_eq!(true, patterns_.is_excluded("/r/baz", true));
+        assert_eq!(false, patterns_.is_excluded("/r/foo", true));
+        assert_eq!(true, patterns_.is_excluded("/r/foo/baz", false));
+        assert_eq!(false, patterns_.is_excluded("/r/foo/bar", true));
+        assert_eq!(false, patterns_.is_excluded("/r/foo/bar/baz", false));
+
+        // The last matching pattern wins
+        let patterns_ = patterns(&["!logs/", "logs/"]);
+        assert_eq!(true, patterns_.is_excluded("/r/logs", true));
+        let patterns_ = patterns(&["logs/", "!logs/"]);
+        assert_eq!(false, patterns_.is_excluded("/r/logs", true));
+        let patterns_ = patterns(&["*.log", "!keep.log", "/x/*.log"]);
+        assert_eq!(false, patterns_.is_excluded("/r/keep.log", false));
+        assert_eq!(true, patterns_.is_excluded("/r/x/keep.log", false));
+
+        // A deeper `.gitignore` overrides its parents
+        let mut nested = Patterns::new("/r/x", &["!keep.log".to_string()]);
+        nested.extend(&patterns(&["*.log"]));
+        assert_eq!(false, nested.is_excluded("/r/x/keep.log", false));
+        assert_eq!(true, nested.is_excluded("/r/x/a.log", false));
+
+        // `**/foo/*` matches the entries of `foo` at any level
+        let patterns_ = patterns(&["**/foo/*"]);
+        assert_eq!(true, patterns_.is_excluded("/r/a/foo/x", false));
+        assert_eq!(false, patterns_.is_excluded("/r/a/foo", true));
+    }
 }
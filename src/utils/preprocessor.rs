@@ -0,0 +1,135 @@
+use std::{
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, Stdio},
+};
+
+use anyhow::Error;
+
+use crate::utils::filters::Filters;
+use crate::utils::lines::{LineIterator, Lines, LinesReader};
+
+#[derive(Clone)]
+pub struct Preprocessor {
+    command: String,
+    filters: Filters,
+}
+
+impl Preprocessor {
+    pub fn new(command: &str, globs: &[String]) -> Result<Self, Error> {
+        // There is no search root to anchor at, slash-containing globs match at any depth
+        let globs = if globs.is_empty() {
+            vec!["*".to_string()]
+        } else {
+            globs
+                .iter()
+                .map(|glob| match glob.trim_start_matches('/') {
+                    glob if glob.starts_with("**/") || !glob.contains('/') => glob.to_owned(),
+                    glob => "**/".to_owned() + glob,
+                })
+                .collect()
+        };
+        Ok(Preprocessor {
+            command: command.to_owned(),
+            filters: Filters::new(&globs, false)?,
+        })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        self.filters.matches(path.to_str().unwrap(), "/")
+    }
+
+    pub fn reader(&self, path: PathBuf) -> Preprocessed {
+        Preprocessed {
+            command: self.command.clone(),
+            path,
+        }
+    }
+}
+
+pub struct Preprocessed {
+    command: String,
+    path: PathBuf,
+}
+
+struct ChildOutput {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for ChildOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for ChildOutput {
+    fn drop(&mut self) {
+        // The output might not be consumed entirely, e.g. with `-l`
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl LinesReader for Preprocessed {
+    fn lines(&self) -> anyhow::Result<Box<LineIterator>> {
+        let mut child = Command::new(&self.command)
+            .arg(&self.path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::new(e).context(format!("failed to spawn '{}'", self.command)))?;
+        let stdout = child.stdout.take().unwrap();
+        Ok(Box::new(Lines::new(
+            BufReader::new(ChildOutput { child, stdout }),
+            self.path.clone(),
+        )))
+    }
+
+    fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn preprocessed_lines() {
+        let dir = env::temp_dir().join(format!("tgrep-preprocessor-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.txt");
+        fs::write(&path, "foo\nbar\n").unwrap();
+
+        let preprocessor = Preprocessor::new("cat", &["*.txt".to_string()]).unwrap();
+        assert!(preprocessor.matches(&path));
+        assert!(!preprocessor.matches(&dir.join("doc.pdf")));
+        let reader = preprocessor.reader(path);
+        let mut lines = reader.lines().unwrap();
+        let mut collected = Vec::new();
+        while let Some(line) = lines.next() {
+            collected.push(line.to_owned());
+        }
+        assert_eq!(vec!["foo", "bar"], collected);
+
+        let reader = Preprocessor::new("/nonexistent/tgrep-pre", &[])
+            .unwrap()
+            .reader(dir.join("doc.txt"));
+        assert!(reader.lines().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn slash_globs() {
+        let preprocessor = Preprocessor::new("cat", &["docs/*.txt".to_string()]).unwrap();
+        assert!(preprocessor.matches(Path::new("/r/docs/a.txt")));
+        assert!(preprocessor.matches(Path::new("/r/x/docs/a.txt")));
+        assert!(!preprocessor.matches(Path::new("/r/src/a.txt")));
+        let preprocessor = Preprocessor::new("cat", &["**/docs/*.txt".to_string()]).unwrap();
+        assert!(preprocessor.matches(Path::new("/r/docs/a.txt")));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use regex::Regex;
+
+use crate::utils::matcher::{Match, Matcher, MatcherOptions};
+
+/// Substitutes matches of the regular expression, `$1`/`${name}` refer to capture groups
+pub struct Replacer {
+    regexp: Regex,
+    replacement: String,
+    modified: AtomicUsize,
+}
+
+impl Replacer {
+    /// Fails if the replacement refers to a group the regular expression doesn't have
+    pub fn new(regexp: Regex, replacement: &str) -> anyhow::Result<Self> {
+        for group in references(replacement) {
+            let known = match group.parse::<usize>() {
+                Ok(index) => index < regexp.captures_len(),
+                Err(_) => regexp.capture_names().flatten().any(|name| name == group),
+            };
+            if !known {
+                anyhow::bail!("the pattern has no capture group '{}'", group);
+            }
+        }
+        Ok(Replacer {
+            regexp,
+            replacement: replacement.to_owned(),
+            modified: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the line with all the matches replaced along with the positions of the replacements
+    pub fn replace_line(&self, line: &str) -> (String, Vec<Match>) {
+        self.replace_first(line, usize::MAX)
+    }
+
+    fn replace_first(&self, line: &str, limit: usize) -> (String, Vec<Match>) {
+        let mut replaced = String::with_capacity(line.len());
+        let mut needles = Vec::new();
+        let mut last = 0;
+        for captures in self.regexp.captures_iter(line).take(limit) {
+            let m = captures.get(0).unwrap();
+            replaced.push_str(&line[last..m.start()]);
+            let start = replaced.len();
+            captures.expand(&self.replacement, &mut replaced);
+            needles.push(Match::new(start, replaced.len()));
+            last = m.end();
+        }
+        replaced.push_str(&line[last..]);
+        (replaced, needles)
+    }
+
+    /// Replaces matches line by line keeping the line terminators intact,
+    /// returns the new content and the number of modified lines.
+    /// Only the lines the matcher accepts are modified, up to as many matches as it reports
+    pub fn replace(&self, content: &str, matcher: &Matcher) -> (String, usize) {
+        let mut replaced = String::with_capacity(content.len());
+        let mut lines = 0;
+        for line in content.split_inclusive('\n') {
+            let (line, terminator) = match line.strip_suffix("\r\n") {
+                Some(line) => (line, "\r\n"),
+                None => match line.strip_suffix('\n') {
+                    Some(line) => (line, "\n"),
+                    None => (line, ""),
+                },
+            };
+            let new = match matcher(line, MatcherOptions::Exact(usize::MAX)) {
+                Some(needles) => self.replace_first(line, needles.len()).0,
+                None => line.to_owned(),
+            };
+            if new != line {
+                lines += 1;
+            }
+            replaced.push_str(&new);
+            replaced.push_str(terminator);
+        }
+        (replaced, lines)
+    }
+
+    pub fn mark_modified(&self) {
+        self.modified.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of files rewritten so far
+    pub fn modified(&self) -> usize {
+        self.modified.load(Ordering::Relaxed)
+    }
+}
+
+/// Groups referred to as `$name` or `${name}`, following `regex::Captures::expand`:
+/// `$$` is a literal dollar and an unbraced name is as long as possible, i.e. `$1a` is `1a`
+fn references(replacement: &str) -> Vec<&str> {
+    let mut references = Vec::new();
+    let mut rest = replacement;
+    while let Some(pos) = rest.find('$') {
+        rest = &rest[pos + 1..];
+        if let Some(stripped) = rest.strip_prefix('$') {
+            rest = stripped;
+        } else if let Some((name, tail)) = rest
+            .strip_prefix('{')
+            .and_then(|braced| braced.split_once('}'))
+        {
+            references.push(name);
+            rest = tail;
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if end > 0 {
+                references.push(&rest[..end]);
+            }
+            rest = &rest[end..];
+        }
+    }
+    references
+}
+
+/// Atomically replaces the file content: the new content is written to a temporary file
+/// next to the original one, synced to disk and renamed over it keeping the permissions
+pub fn write(path: &Path, content: &str) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let tmp = path.with_file_name(format!(
+        ".{}.tgrep-{}",
+        name.to_string_lossy(),
+        process::id()
+    ));
+    let res = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        file.set_permissions(fs::metadata(path)?.permissions())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, sync::Arc};
+
+    use super::*;
+
+    #[test]
+    fn replace() {
+        let replacer = Replacer::new(Regex::new(r"(\w+)@(\w+)").unwrap(), "$2 at $1").unwrap();
+        let (line, needles) = replacer.replace_line("mail foo@bar or baz@qux");
+        assert_eq!("mail bar at foo or qux at baz", line);
+        let needles: Vec<_> = needles.iter().map(|m| (m.start(), m.end())).collect();
+        assert_eq!(vec![(5, 15), (19, 29)], needles);
+
+        let matcher = |limit: usize| -> Matcher {
+            let regexp = Regex::new(r"\w+@\w+").unwrap();
+            Arc::new(Box::new(move |line: &str, _| {
+                let needles: Vec<_> = regexp
+                    .find_iter(line)
+                    .take(limit)
+                    .map(|m| Match::new(m.start(), m.end()))
+                    .collect();
+                (!needles.is_empty()).then_some(needles)
+            }))
+        };
+        let (content, lines) = replacer.replace("a@b\r\nc\nd@e", &matcher(usize::MAX));
+        assert_eq!("b at a\r\nc\ne at d", content);
+        assert_eq!(2, lines);
+        // Only the matches the matcher reports are replaced
+        let (limited, lines) = replacer.replace("a@b c@d\ne@f", &matcher(1));
+        assert_eq!("b at a c@d\nf at e", limited);
+        assert_eq!(2, lines);
+
+        let path = env::temp_dir().join(format!("tgrep-replace-{}", process::id()));
+        fs::write(&path, "old").unwrap();
+        write(&path, &content).unwrap();
+        assert_eq!(content, fs::read_to_string(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn named_groups() {
+        let regexp = Regex::new(r"(?P<year>\d{4})-(?P<month>\d{2})").unwrap();
+        let replacer = Replacer::new(regexp.clone(), "${month}/${year} $$5").unwrap();
+        let (line, _) = replacer.replace_line("since 2021-03");
+        assert_eq!("since 03/2021 $5", line);
+
+        assert!(Replacer::new(regexp.clone(), "${day}").is_err());
+        assert!(Replacer::new(regexp.clone(), "$3").is_err());
+        // `$1x` refers to a group named `1x`, as with `regex::Captures::expand`
+        assert!(Replacer::new(regexp.clone(), "$1x").is_err());
+        assert!(Replacer::new(regexp, "${1}x $0").is_ok());
+    }
+}
@@ -1,53 +1,343 @@
 use std::{
-    collections::BTreeMap,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
     env,
     fs::{self, DirEntry},
     io,
     path::{Path, PathBuf},
     rc::Rc,
-    sync::atomic::{AtomicBool, Ordering},
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
     sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
+use content_inspector::ContentType;
 use crossbeam::sync::WaitGroup;
-use futures::executor::ThreadPool;
+use encoding_rs::{UTF_16BE, UTF_16LE};
+pub use futures::executor::ThreadPool;
 use log::{debug, error, info, warn};
 
-use crate::utils::display::Display;
+#[cfg(feature = "archive")]
+use crate::utils::archive;
+use crate::utils::decoded::{Decoded, Encoding};
+use crate::utils::display::{Display, FileInfo, FileKind};
 use crate::utils::filters::Filters;
+use crate::utils::gitdiff::GitTracked;
 use crate::utils::grep::Grep;
-use crate::utils::lines::Zero;
+use crate::utils::gzipped::Gzipped;
+use crate::utils::lines::{LinesReader, Zero};
 use crate::utils::mapped::Mapped;
-use crate::utils::matcher::Matcher;
+use crate::utils::matcher::{MatchLimit, Matcher, MatcherOptions};
 use crate::utils::patterns::{Patterns, ToPatterns};
+use crate::utils::preprocessor::Preprocessor;
 use crate::utils::writer::BufferedWriter;
 
 static GIT_IGNORE: &str = ".gitignore";
 pub const GIT_DIR: &str = ".git";
 
+#[derive(Clone, Copy)]
+pub enum FilenameMatch {
+    /// The path relative to the searched directory, or for a searched file
+    /// relative to the current directory
+    Path,
+    Basename,
+}
+
+/// Entries reported by the filename match, symlinks are tested by their own name
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryType {
+    File,
+    Dir,
+    Symlink,
+    Any,
+}
+
+impl FromStr for EntryType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(EntryType::File),
+            "dir" => Ok(EntryType::Dir),
+            "symlink" => Ok(EntryType::Symlink),
+            "any" => Ok(EntryType::Any),
+            _ => anyhow::bail!("unknown entry type '{}'", s),
+        }
+    }
+}
+
+impl EntryType {
+    fn accepts(&self, file_type: fs::FileType) -> bool {
+        match self {
+            EntryType::File => file_type.is_file(),
+            EntryType::Dir => file_type.is_dir(),
+            EntryType::Symlink => file_type.is_symlink(),
+            EntryType::Any => true,
+        }
+    }
+
+    fn accepts_files(&self) -> bool {
+        matches!(self, EntryType::File | EntryType::Any)
+    }
+}
+
+/// Extensions of the files skipped by name with `WalkerBuilder::skip_binary_ext`
+const BINARY_EXTENSIONS: &[&str] = &[
+    "7z", "a", "avi", "bin", "bmp", "bz2", "class", "db", "dll", "dylib", "exe", "gif", "ico",
+    "jar", "jpeg", "jpg", "mkv", "mov", "mp3", "mp4", "o", "otf", "pdf", "png", "pyc", "rar", "so",
+    "sqlite", "tiff", "ttf", "wasm", "webp", "woff", "woff2", "xz", "zip",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortBy {
+    Count,
+    Modified,
+}
+
+impl FromStr for SortBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(SortBy::Count),
+            "modified" => Ok(SortBy::Modified),
+            _ => anyhow::bail!("unknown sort key '{}'", s),
+        }
+    }
+}
+
+/// Progress of the search of a file.
+/// Both events of a file are reported from the thread searching it, `Started` first,
+/// the events of different files interleave when they are searched in the thread pool.
+/// All the events of a walk are reported by the time `Walker::walk` returns.
+pub enum FileEvent<'a> {
+    Started(&'a Path),
+    /// Carries the number of matching lines, `None` if the file was skipped
+    Finished(&'a Path, Option<usize>),
+}
+
+pub type OnFile = Arc<dyn Fn(FileEvent) + Send + Sync>;
+
+/// What the walk did with an entry when searching contents, ignored directories are reported
+/// but not their content. Entries listed with `list_files` or matched with `filename_match`
+/// are not reported, nor are files that fail to be read
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    Searched,
+    /// Excluded by an ignore or exclude pattern
+    Ignored,
+    /// Skipped by extension or content, unless binary files are searched
+    Binary,
+    /// Not selected by the file filters or the modification time window, or not tracked by git
+    Filtered,
+}
+
+/// Reported from the thread deciding, files are searched in the thread pool
+pub type OnConsidered = Arc<dyn Fn(&Path, Decision) + Send + Sync>;
+
+struct FileOutput {
+    path: Arc<PathBuf>,
+    modified: Option<SystemTime>,
+    matches: Arc<AtomicUsize>,
+    writer: Arc<BufferedWriter>,
+}
+
+impl FileOutput {
+    fn matches(&self) -> usize {
+        self.matches.load(Ordering::Relaxed)
+    }
+}
+
+/// The output of the files held back to be sorted, shared by the walkers of the searched
+/// paths so that it is sorted across all of them
+#[derive(Clone, Default)]
+pub struct SortedOutputs(Rc<RefCell<Vec<FileOutput>>>);
+
+#[derive(Clone, Default)]
+struct ReaderOptions {
+    binary_grep: Option<Grep>,
+    search_zip: bool,
+    #[cfg(feature = "archive")]
+    search_archive: bool,
+    preprocessor: Option<Arc<Preprocessor>>,
+    encoding: Option<Encoding>,
+    encoding_fallback: Option<&'static encoding_rs::Encoding>,
+    no_mmap: Option<Arc<Filters>>,
+    mmap_populate: bool,
+    on_file: Option<OnFile>,
+    on_considered: Option<OnConsidered>,
+}
+
+impl ReaderOptions {
+    fn consider(&self, path: &Path, decision: Decision) {
+        if let Some(on_considered) = &self.on_considered {
+            on_considered(path, decision);
+        }
+    }
+
+    fn no_mmap(&self, path: &Path) -> bool {
+        self.no_mmap
+            .as_ref()
+            .is_some_and(|no_mmap| no_mmap.matches(path.to_str().unwrap(), "/"))
+    }
+
+    /// Binary gzipped files are skipped as plain ones, unless searched
+    fn skips_binary(&self, gzipped: &Gzipped) -> bool {
+        self.binary_grep.is_none() && gzipped.is_binary().unwrap_or(false)
+    }
+
+    #[cfg(feature = "archive")]
+    fn search_archive(&self, path: &Path) -> bool {
+        self.search_archive && archive::is_archive(path)
+    }
+}
+
+/// Walk counters, shared by all the walkers built with the same `Stats`.
+/// They are updated atomically from the thread pool, so they may be read at any time,
+/// but are exact only once `Walker::walk` has returned, i.e. after the `WaitGroup` joined.
+pub struct Stats {
+    files_searched: AtomicUsize,
+    files_matched: AtomicUsize,
+    matches: AtomicUsize,
+    files_skipped: AtomicUsize,
+    bytes_scanned: AtomicUsize,
+    started: Instant,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            files_searched: Default::default(),
+            files_matched: Default::default(),
+            matches: Default::default(),
+            files_skipped: Default::default(),
+            bytes_scanned: Default::default(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Stats {
+    /// Number of files read
+    pub fn files_searched(&self) -> usize {
+        self.files_searched.load(Ordering::Relaxed)
+    }
+
+    /// Number of searched files with at least one match
+    pub fn files_matched(&self) -> usize {
+        self.files_matched.load(Ordering::Relaxed)
+    }
+
+    /// Number of matching lines over all the searched files
+    pub fn matches(&self) -> usize {
+        self.matches.load(Ordering::Relaxed)
+    }
+
+    /// Number of ignored paths and binary files that were not searched
+    pub fn files_skipped(&self) -> usize {
+        self.files_skipped.load(Ordering::Relaxed)
+    }
+
+    /// Size on disk of the searched files
+    pub fn bytes_scanned(&self) -> usize {
+        self.bytes_scanned.load(Ordering::Relaxed)
+    }
+
+    /// Time since the stats were created
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    fn skip(&self) {
+        self.files_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of matching lines, `None` standing for a skipped file
+    pub(crate) fn record(&self, len: usize, count: Option<usize>) -> usize {
+        match count {
+            Some(count) => {
+                self.files_searched.fetch_add(1, Ordering::Relaxed);
+                self.bytes_scanned.fetch_add(len, Ordering::Relaxed);
+                if count > 0 {
+                    self.files_matched.fetch_add(1, Ordering::Relaxed);
+                    self.matches.fetch_add(count, Ordering::Relaxed);
+                }
+                count
+            }
+            None => {
+                self.skip();
+                0
+            }
+        }
+    }
+}
+
+/// `.gitignore` files of the parents of the searched paths by directory,
+/// each is parsed once however many path arguments share it
+pub struct IgnoreCache {
+    negations: bool,
+    patterns: HashMap<PathBuf, Option<Patterns>>,
+}
+
+impl IgnoreCache {
+    pub fn new(negations: bool) -> Self {
+        IgnoreCache {
+            negations,
+            patterns: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, dir: &Path) -> Option<Patterns> {
+        let negations = self.negations;
+        self.patterns
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| Walker::process_gitignore(dir, negations))
+            .clone()
+    }
+}
+
 #[derive(Clone)]
 pub struct Walker {
     tpool: Option<ThreadPool>,
     ignore_patterns: Arc<Patterns>,
     force_ignore_patterns: Arc<Patterns>,
     file_filters: Arc<Filters>,
+    ignore_files: bool,
+    negated_ignore: bool,
     grep: Grep,
     matcher: Matcher,
     ignore_symlinks: bool,
+    match_limit: Option<MatchLimit>,
+    filename_match: Option<FilenameMatch>,
+    filename_match_type: EntryType,
+    list_files: bool,
+    binary_extensions: Option<Arc<HashSet<String>>>,
+    git_tracked: Option<Arc<GitTracked>>,
+    reader_options: ReaderOptions,
     display: Arc<dyn Display>,
     print_file_separator: bool,
+    separator_after_last: bool,
+    line_buffered: bool,
     file_separator_printed: Rc<AtomicBool>,
+    sort_by: Option<SortBy>,
+    sort_reverse: bool,
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+    sorted_outputs: SortedOutputs,
+    stats: Arc<Stats>,
 }
 
 pub struct WalkerBuilder(Walker);
 
 impl WalkerBuilder {
     pub fn new(grep: Grep, matcher: Matcher, display: Arc<dyn Display>) -> Self {
-        WalkerBuilder {
-            0: Walker::new(grep, matcher, display),
-        }
+        WalkerBuilder(Walker::new(grep, matcher, display))
     }
 
+    /// Files are searched on the pool, or on the calling thread without one.
+    /// The pool is cheap to clone, so that one can serve any number of walks,
+    /// each of which still waits for its own files only.
     pub fn thread_pool(mut self, tpool: ThreadPool) -> WalkerBuilder {
         self.0.tpool = Some(tpool);
         self
@@ -63,6 +353,17 @@ impl WalkerBuilder {
         self
     }
 
+    pub fn ignore_files(mut self, ignore_files: bool) -> WalkerBuilder {
+        self.0.ignore_files = ignore_files;
+        self
+    }
+
+    /// Whether the `!` patterns of `.gitignore` files re-include paths
+    pub fn negated_ignore(mut self, negated_ignore: bool) -> WalkerBuilder {
+        self.0.negated_ignore = negated_ignore;
+        self
+    }
+
     pub fn file_filters(mut self, file_filters: Filters) -> WalkerBuilder {
         self.0.file_filters = Arc::new(file_filters);
         self
@@ -73,11 +374,157 @@ impl WalkerBuilder {
         self
     }
 
+    pub fn match_limit(mut self, match_limit: Option<MatchLimit>) -> WalkerBuilder {
+        self.0.match_limit = match_limit;
+        self
+    }
+
+    pub fn filename_match(mut self, filename_match: Option<FilenameMatch>) -> WalkerBuilder {
+        self.0.filename_match = filename_match;
+        self
+    }
+
+    /// Entries the filename match reports, regular files only by default
+    pub fn filename_match_type(mut self, filename_match_type: EntryType) -> WalkerBuilder {
+        self.0.filename_match_type = filename_match_type;
+        self
+    }
+
+    /// Lists the files that would be searched instead of searching them
+    pub fn list_files(mut self, list_files: bool) -> WalkerBuilder {
+        self.0.list_files = list_files;
+        self
+    }
+
+    /// Skips the files with a common binary extension, or one of `extra`, without reading them.
+    /// Files named on the command line are still searched
+    pub fn skip_binary_ext(mut self, skip: bool, extra: &[String]) -> WalkerBuilder {
+        self.0.binary_extensions = skip.then(|| {
+            let extensions = BINARY_EXTENSIONS.iter().map(|ext| ext.to_string());
+            let extra = extra
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_lowercase());
+            Arc::new(extensions.chain(extra).collect())
+        });
+        self
+    }
+
+    /// Skips the files of the walked directories that git doesn't track
+    pub fn git_tracked(mut self, git_tracked: Option<Arc<GitTracked>>) -> WalkerBuilder {
+        self.0.git_tracked = git_tracked;
+        self
+    }
+
+    /// Binary files are skipped unless a grep for them is provided
+    pub fn binary_grep(mut self, binary_grep: Option<Grep>) -> WalkerBuilder {
+        self.0.reader_options.binary_grep = binary_grep;
+        self
+    }
+
+    pub fn search_zip(mut self, search_zip: bool) -> WalkerBuilder {
+        self.0.reader_options.search_zip = search_zip;
+        self
+    }
+
+    /// Members of tar archives are searched as files named `archive.tar:member`
+    #[cfg(feature = "archive")]
+    pub fn search_archive(mut self, search_archive: bool) -> WalkerBuilder {
+        self.0.reader_options.search_archive = search_archive;
+        self
+    }
+
+    pub fn preprocessor(mut self, preprocessor: Option<Preprocessor>) -> WalkerBuilder {
+        self.0.reader_options.preprocessor = preprocessor.map(Arc::new);
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Option<Encoding>) -> WalkerBuilder {
+        self.0.reader_options.encoding = encoding;
+        self
+    }
+
+    /// Lines of mapped files that are not valid UTF-8 are decoded with the fallback encoding
+    pub fn encoding_fallback(
+        mut self,
+        encoding_fallback: Option<&'static encoding_rs::Encoding>,
+    ) -> WalkerBuilder {
+        self.0.reader_options.encoding_fallback = encoding_fallback;
+        self
+    }
+
+    /// Files matching the filters are read with buffered reads, e.g. virtual files mmap hangs on
+    pub fn no_mmap(mut self, no_mmap: Option<Filters>) -> WalkerBuilder {
+        self.0.reader_options.no_mmap = no_mmap.map(Arc::new);
+        self
+    }
+
+    pub fn mmap_populate(mut self, mmap_populate: bool) -> WalkerBuilder {
+        self.0.reader_options.mmap_populate = mmap_populate;
+        self
+    }
+
+    /// Reports the start and the end of the search of every file
+    pub fn on_file(mut self, on_file: Option<OnFile>) -> WalkerBuilder {
+        self.0.reader_options.on_file = on_file;
+        self
+    }
+
+    /// Reports the decision taken for each entry of the walked directories, see `Decision`
+    pub fn on_considered(mut self, on_considered: Option<OnConsidered>) -> WalkerBuilder {
+        self.0.reader_options.on_considered = on_considered;
+        self
+    }
+
     pub fn print_file_separator(mut self, print_file_separator: bool) -> WalkerBuilder {
         self.0.print_file_separator = print_file_separator;
         self
     }
 
+    /// Also prints the file separator after the output of the last file
+    pub fn separator_after_last(mut self, separator_after_last: bool) -> WalkerBuilder {
+        self.0.separator_after_last = separator_after_last;
+        self
+    }
+
+    /// Writes the lines as they are found instead of buffering the output of every file,
+    /// the output of the files searched concurrently interleaves unless the pool has one thread
+    pub fn line_buffered(mut self, line_buffered: bool) -> WalkerBuilder {
+        self.0.line_buffered = line_buffered;
+        self
+    }
+
+    /// Only files modified at or after the time are searched
+    pub fn modified_after(mut self, modified_after: Option<SystemTime>) -> WalkerBuilder {
+        self.0.modified_after = modified_after;
+        self
+    }
+
+    /// Only files modified before the time are searched
+    pub fn modified_before(mut self, modified_before: Option<SystemTime>) -> WalkerBuilder {
+        self.0.modified_before = modified_before;
+        self
+    }
+
+    pub fn sort_by(mut self, sort_by: Option<SortBy>) -> WalkerBuilder {
+        self.0.sort_by = sort_by;
+        self
+    }
+
+    pub fn sort_reverse(mut self, sort_reverse: bool) -> WalkerBuilder {
+        self.0.sort_reverse = sort_reverse;
+        self
+    }
+
+    pub fn sorted_outputs(mut self, sorted_outputs: SortedOutputs) -> WalkerBuilder {
+        self.0.sorted_outputs = sorted_outputs;
+        self
+    }
+
+    pub fn stats(mut self, stats: Arc<Stats>) -> WalkerBuilder {
+        self.0.stats = stats;
+        self
+    }
+
     pub fn build(self) -> Walker {
         self.0
     }
@@ -90,15 +537,38 @@ impl Walker {
             ignore_patterns: Default::default(),
             force_ignore_patterns: Default::default(),
             file_filters: Default::default(),
+            ignore_files: true,
+            negated_ignore: true,
             grep,
             matcher,
             ignore_symlinks: false,
+            match_limit: None,
+            filename_match: None,
+            filename_match_type: EntryType::File,
+            list_files: false,
+            binary_extensions: None,
+            git_tracked: None,
+            reader_options: Default::default(),
             display,
             print_file_separator: false,
+            separator_after_last: false,
+            line_buffered: false,
             file_separator_printed: Default::default(),
+            sort_by: None,
+            sort_reverse: false,
+            modified_after: None,
+            modified_before: None,
+            sorted_outputs: Default::default(),
+            stats: Default::default(),
         }
     }
 
+    fn is_limit_reached(&self) -> bool {
+        self.match_limit
+            .as_ref()
+            .is_some_and(|match_limit| match_limit.reached())
+    }
+
     fn is_ignore_file(&self, entry: &DirEntry) -> bool {
         Some(GIT_IGNORE) == entry.file_name().to_str()
     }
@@ -117,13 +587,13 @@ impl Walker {
         skip
     }
 
-    fn process_gitignore(path: &Path) -> Option<Patterns> {
+    fn process_gitignore(path: &Path, negations: bool) -> Option<Patterns> {
         let ifile = {
             let mut ifile = path.to_path_buf();
             ifile.push(GIT_IGNORE);
             ifile
         };
-        match ifile.to_patterns() {
+        match ifile.to_patterns(negations) {
             Ok(ignore_patterns) => Some(ignore_patterns),
             Err(e) => {
                 match e.downcast_ref::<io::Error>() {
@@ -141,10 +611,43 @@ impl Walker {
         path.exists()
     }
 
+    /// The closest of the path and its parents containing a `.git` directory
+    pub fn find_repo_root(path: &Path) -> Option<PathBuf> {
+        path.ancestors()
+            .find(|path| Self::contains_git_dir(path))
+            .map(Path::to_path_buf)
+    }
+
+    /// Files with no modification time are skipped once a bound is set
+    fn is_modified_in_window(&self, modified: Option<SystemTime>) -> bool {
+        match modified {
+            Some(modified) => {
+                self.modified_after.is_none_or(|after| modified >= after)
+                    && self.modified_before.is_none_or(|before| modified < before)
+            }
+            None => self.modified_after.is_none() && self.modified_before.is_none(),
+        }
+    }
+
+    fn has_binary_extension(&self, path: &Path) -> bool {
+        let extensions = match &self.binary_extensions {
+            Some(extensions) => extensions,
+            None => return false,
+        };
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| extensions.contains(&ext.to_lowercase()))
+    }
+
     fn walk_dir(&self, path: &Path, parents: &[PathBuf]) {
         let walker = {
             let mut walker = self.clone();
-            if let Some(mut ignore_patterns) = Self::process_gitignore(path) {
+            let ignore_patterns = if self.ignore_files {
+                Self::process_gitignore(path, self.negated_ignore)
+            } else {
+                None
+            };
+            if let Some(mut ignore_patterns) = ignore_patterns {
                 ignore_patterns.extend(&walker.ignore_patterns);
                 walker.ignore_patterns = Arc::new(ignore_patterns);
             }
@@ -153,6 +656,8 @@ impl Walker {
 
         let mut to_dive = BTreeMap::new();
         let mut to_grep = Vec::new();
+        // Directories and symlinks whose names are matched, symlinks are still followed
+        let mut to_match = Vec::new();
 
         let entries: Vec<_> = fs::read_dir(path)
             .unwrap()
@@ -165,16 +670,49 @@ impl Walker {
                     None
                 }
             })
-            .filter(|(entry, meta)| !walker.is_excluded(entry, meta.is_dir()))
+            .filter(|(entry, meta)| {
+                let excluded = walker.is_excluded(entry, meta.is_dir());
+                if excluded {
+                    self.stats.skip();
+                    self.reader_options.consider(entry, Decision::Ignored);
+                }
+                !excluded
+            })
             .collect();
+        let root = parents.first().map(|root| root.as_path()).unwrap_or(path);
         for (path, meta) in entries {
             let file_type = meta.file_type();
             if file_type.is_file() {
-                if !self.file_filters.matches(path.to_str().unwrap()) {
+                if !self
+                    .file_filters
+                    .matches(path.to_str().unwrap(), root.to_str().unwrap())
+                {
+                    self.reader_options.consider(&path, Decision::Filtered);
+                    continue;
+                }
+                if let Some(git_tracked) = &self.git_tracked {
+                    if !git_tracked.contains(&path) {
+                        debug!("Skipping untracked file '{}'", path.display());
+                        self.reader_options.consider(&path, Decision::Filtered);
+                        continue;
+                    }
+                }
+                if self.has_binary_extension(&path) {
+                    debug!("Skipping binary file '{}'", path.display());
+                    self.stats.skip();
+                    self.reader_options.consider(&path, Decision::Binary);
                     continue;
                 }
-                to_grep.push((path, meta.len() as usize));
+                let modified = meta.modified().ok();
+                if !self.is_modified_in_window(modified) {
+                    self.reader_options.consider(&path, Decision::Filtered);
+                    continue;
+                }
+                to_grep.push((path, meta.len() as usize, modified));
             } else {
+                if self.filename_match.is_some() && self.filename_match_type.accepts(file_type) {
+                    to_match.push(path.clone());
+                }
                 to_dive.insert(path, meta);
             }
         }
@@ -185,67 +723,327 @@ impl Walker {
             parents
         };
         for (entry, meta) in to_dive {
+            if self.is_limit_reached() {
+                return;
+            }
             walker.walk_with_parents(&entry, Some(meta), &parents);
         }
 
-        self.grep_many(&to_grep);
+        if self.list_files {
+            to_grep.sort();
+            for (entry, len, _) in to_grep {
+                self.list_file(&entry, len);
+            }
+            return;
+        }
+        match self.filename_match {
+            Some(mode) => {
+                if self.filename_match_type.accepts_files() {
+                    to_match.extend(to_grep.into_iter().map(|(entry, _, _)| entry));
+                }
+                to_match.sort();
+                for entry in to_match {
+                    self.match_filename(&entry, &parents[0], mode);
+                }
+            }
+            None => self.grep_many(&to_grep),
+        }
+    }
+
+    fn match_filename(&self, path: &Path, root: &Path, mode: FilenameMatch) {
+        let name = match mode {
+            FilenameMatch::Path => path.strip_prefix(root).unwrap_or(path),
+            FilenameMatch::Basename => Path::new(path.file_name().unwrap()),
+        };
+        if (self.matcher)(name.to_str().unwrap(), MatcherOptions::Exact(usize::MAX)).is_some() {
+            self.display.display(path, None);
+        }
+    }
+
+    /// Mirrors the reader selection of `Walker::grep`
+    fn list_file(&self, path: &Path, len: usize) {
+        let options = &self.reader_options;
+        let file_info = |kind, mapped| {
+            let info = FileInfo {
+                size: len,
+                kind,
+                mapped,
+            };
+            self.display.file_info(path, &info);
+        };
+        if options
+            .preprocessor
+            .as_ref()
+            .is_some_and(|preprocessor| preprocessor.matches(path))
+        {
+            return file_info(FileKind::Preprocessed, false);
+        }
+        #[cfg(feature = "archive")]
+        if options.search_archive(path) {
+            return file_info(FileKind::Archive, false);
+        }
+        let (kind, mapped) = if len == 0 {
+            (FileKind::Empty, false)
+        } else if options.no_mmap(path) {
+            if options.search_zip && Gzipped::has_extension(path) {
+                let gzipped = Gzipped::new(path.to_owned());
+                if options.skips_binary(&gzipped) {
+                    return;
+                }
+                (FileKind::Gzip, false)
+            } else {
+                (FileKind::Text, false)
+            }
+        } else {
+            // Only the beginning is inspected
+            match Mapped::new(path, len, false) {
+                Ok(mapped) if options.search_zip && Gzipped::is_gzipped(path, &mapped) => {
+                    let gzipped = Gzipped::from_mapped(path.to_owned(), mapped);
+                    if options.skips_binary(&gzipped) {
+                        return;
+                    }
+                    (FileKind::Gzip, true)
+                }
+                Ok(mapped) if content_inspector::inspect(&mapped).is_binary() => {
+                    if options.binary_grep.is_none() {
+                        return;
+                    }
+                    (FileKind::Binary, true)
+                }
+                Ok(_) => (FileKind::Text, true),
+                Err(_) => (FileKind::Text, false),
+            }
+        };
+        file_info(kind, mapped);
     }
 
+    /// Returns the number of matching lines, `None` if the file was skipped
     fn grep(
         grep: Grep,
         entry: Arc<PathBuf>,
         len: usize,
         matcher: Matcher,
         display: Arc<dyn Display>,
-    ) {
-        match Mapped::new(&entry, len) {
+        options: ReaderOptions,
+    ) -> Option<usize> {
+        let on_file = options.on_file.clone();
+        let on_considered = options.on_considered.clone();
+        if let Some(on_file) = &on_file {
+            on_file(FileEvent::Started(&entry));
+        }
+        let count = if display.file_events() {
+            // Only the files with some output are enclosed, which is known once searched
+            let buffer = Arc::new(BufferedWriter::new());
+            let file_display = display.with_writer(buffer.clone());
+            let count = Walker::read(grep, entry.clone(), len, matcher, file_display, options);
+            if let (Some(matches), true) = (count, buffer.has_some()) {
+                display.file_begin(&entry);
+                buffer.flush(&display.writer());
+                display.file_end(&entry, matches, len);
+            }
+            count
+        } else {
+            Walker::read(grep, entry.clone(), len, matcher, display, options)
+        };
+        if let (Some(on_considered), Some(_)) = (&on_considered, count) {
+            on_considered(&entry, Decision::Searched);
+        }
+        if let Some(on_file) = &on_file {
+            on_file(FileEvent::Finished(&entry, count));
+        }
+        count
+    }
+
+    fn read(
+        grep: Grep,
+        entry: Arc<PathBuf>,
+        len: usize,
+        matcher: Matcher,
+        display: Arc<dyn Display>,
+        options: ReaderOptions,
+    ) -> Option<usize> {
+        if len == 0 {
+            // Empty files can't be mapped, `Zero` yields no lines so that `-L` still lists them
+            return Some((grep)(
+                Arc::new(Zero::new((*entry).clone())),
+                matcher,
+                display,
+            ));
+        }
+        if let Some(preprocessor) = &options.preprocessor {
+            if preprocessor.matches(&entry) {
+                return Some((grep)(
+                    Arc::new(preprocessor.reader((*entry).clone())),
+                    matcher,
+                    display,
+                ));
+            }
+        }
+        #[cfg(feature = "archive")]
+        if options.search_archive(&entry) {
+            let mut count = 0;
+            let res = archive::members(&entry, |member| {
+                count += (grep)(Arc::new(member), matcher.clone(), display.clone());
+            });
+            if let Err(e) = res {
+                warn!("Failed to read archive '{}': {}", entry.display(), e);
+            }
+            return Some(count);
+        }
+        if options.no_mmap(&entry) {
+            if options.search_zip && Gzipped::has_extension(&entry) {
+                let gzipped = Gzipped::new((*entry).clone());
+                return Walker::grep_gzipped(grep, gzipped, matcher, display, &options);
+            }
+            // Neither binary detection nor decoding, as when mapping fails
+            return Some((grep)(entry, matcher, display));
+        }
+        match Mapped::new(&entry, len, options.mmap_populate) {
             Ok(mapped) => {
-                if content_inspector::inspect(&*mapped).is_binary() {
-                    debug!("Skipping binary file '{}'", entry.display());
-                    return;
+                if options.search_zip && Gzipped::is_gzipped(&entry, &mapped) {
+                    let gzipped = Gzipped::from_mapped((*entry).clone(), mapped);
+                    return Walker::grep_gzipped(grep, gzipped, matcher, display, &options);
                 }
-                (grep)(Arc::new(mapped), matcher, display);
-            }
-            Err(e) => {
-                warn!("Failed to map file '{}': {}", entry.display(), e);
-                (grep)(entry, matcher, display);
+                let content_type = content_inspector::inspect(&mapped);
+                let grep = if content_type.is_binary() {
+                    match options.binary_grep {
+                        Some(binary_grep) => binary_grep,
+                        None => {
+                            debug!("Skipping binary file '{}'", entry.display());
+                            options.consider(&entry, Decision::Binary);
+                            return None;
+                        }
+                    }
+                } else {
+                    if let Some(encoding) = options.encoding {
+                        let decoded = Decoded::new((*entry).clone(), &mapped, encoding);
+                        return Some((grep)(Arc::new(decoded), matcher, display));
+                    }
+                    // Told apart by the byte order mark
+                    let utf16 = match content_type {
+                        ContentType::UTF_16LE => Some(UTF_16LE),
+                        ContentType::UTF_16BE => Some(UTF_16BE),
+                        _ => None,
+                    };
+                    if let Some(encoding) = utf16 {
+                        let mapped = mapped.into_utf16(encoding);
+                        #[allow(clippy::arc_with_non_send_sync)]
+                        return Some((grep)(Arc::new(mapped), matcher, display));
+                    }
+                    grep
+                };
+                let mapped = match options.encoding_fallback {
+                    Some(encoding) if !content_type.is_binary() => mapped.fallback(encoding),
+                    _ => mapped,
+                };
+                #[allow(clippy::arc_with_non_send_sync)]
+                Some((grep)(Arc::new(mapped), matcher, display))
             }
+            Err(e) => match e.downcast_ref::<io::Error>() {
+                // Reading would fail the same way
+                Some(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    warn!("Skipping '{}': {}", entry.display(), e);
+                    None
+                }
+                _ => {
+                    warn!("Failed to map file '{}': {}", entry.display(), e);
+                    Some((grep)(entry, matcher, display))
+                }
+            },
         }
     }
 
-    fn grep_many(&self, entries: &[(PathBuf, usize)]) {
-        let writer = self.display.writer();
-        let mut writers = BTreeMap::new();
+    /// The decompressed content is inspected for binary data as plain files are
+    fn grep_gzipped(
+        grep: Grep,
+        gzipped: Gzipped,
+        matcher: Matcher,
+        display: Arc<dyn Display>,
+        options: &ReaderOptions,
+    ) -> Option<usize> {
+        let grep = match gzipped.is_binary() {
+            Ok(true) => match &options.binary_grep {
+                Some(binary_grep) => binary_grep.clone(),
+                None => {
+                    debug!("Skipping binary file '{}'", gzipped.path().display());
+                    options.consider(gzipped.path(), Decision::Binary);
+                    return None;
+                }
+            },
+            // Failures are reported by the grep
+            Ok(false) | Err(_) => grep,
+        };
+        #[allow(clippy::arc_with_non_send_sync)]
+        Some((grep)(Arc::new(gzipped), matcher, display))
+    }
+
+    fn grep_many(&self, entries: &[(PathBuf, usize, Option<SystemTime>)]) {
+        let mut outputs = BTreeMap::new();
         let wg = WaitGroup::new();
-        for (entry, len) in entries {
+        for (entry, len, modified) in entries {
+            if self.is_limit_reached() {
+                break;
+            }
             let entry = Arc::new(entry.clone());
             let matcher = self.matcher.clone();
-            let writer = Arc::new(BufferedWriter::new());
-            let display = self.display.with_writer(writer.clone());
-            writers.insert(entry.clone(), writer);
+            let matches = Arc::new(AtomicUsize::new(0));
+            let display = if self.line_buffered {
+                // Nothing to flush, neither sorting nor file separators apply
+                self.display.clone()
+            } else {
+                let writer = Arc::new(BufferedWriter::new());
+                outputs.insert(
+                    entry.clone(),
+                    FileOutput {
+                        path: entry.clone(),
+                        modified: *modified,
+                        matches: matches.clone(),
+                        writer: writer.clone(),
+                    },
+                );
+                self.display.with_writer(writer)
+            };
             let len = *len;
-            if len == 0 {
-                (self.grep)(Arc::new(Zero::new((*entry).clone())), matcher, display);
-                continue;
-            }
-            if entries.len() < 3 {
-                Walker::grep(self.grep.clone(), entry, len, matcher, display);
+            let options = self.reader_options.clone();
+            // Empty files are not worth a task
+            if len == 0 || entries.len() < 3 {
+                let count = Walker::grep(self.grep.clone(), entry, len, matcher, display, options);
+                matches.store(self.stats.record(len, count), Ordering::Relaxed);
                 continue;
             }
             match &self.tpool {
                 Some(tpool) => {
                     let grep = self.grep.clone();
+                    let stats = self.stats.clone();
                     let wg = wg.clone();
                     tpool.spawn_ok(async move {
-                        Walker::grep(grep, entry, len, matcher, display);
+                        let count = Walker::grep(grep, entry, len, matcher, display, options);
+                        matches.store(stats.record(len, count), Ordering::Relaxed);
                         drop(wg);
                     });
                 }
-                None => Walker::grep(self.grep.clone(), entry, len, matcher, display),
+                None => {
+                    let count =
+                        Walker::grep(self.grep.clone(), entry, len, matcher, display, options);
+                    matches.store(self.stats.record(len, count), Ordering::Relaxed);
+                }
             }
         }
         wg.wait();
-        for (_, w) in writers {
+        if self.sort_by.is_some() {
+            self.sorted_outputs
+                .0
+                .borrow_mut()
+                .extend(outputs.into_values());
+        } else {
+            self.flush(outputs.into_values());
+        }
+    }
+
+    fn flush(&self, outputs: impl IntoIterator<Item = FileOutput>) {
+        let writer = self.display.writer();
+        for output in outputs {
+            let w = output.writer;
             if self.print_file_separator
                 && w.has_some()
                 && self.file_separator_printed.swap(true, Ordering::Relaxed)
@@ -256,12 +1054,44 @@ impl Walker {
         }
     }
 
+    /// Flushes the output held back by the walks of all the walkers sharing the sorted outputs
+    pub fn flush_sorted(&self) {
+        let Some(sort_by) = self.sort_by else {
+            return;
+        };
+        let mut outputs = self.sorted_outputs.0.take();
+        outputs.sort_by(|a, b| {
+            let ordering = match sort_by {
+                SortBy::Count => b.matches().cmp(&a.matches()),
+                SortBy::Modified => a.modified.cmp(&b.modified),
+            };
+            let ordering = if self.sort_reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            ordering.then_with(|| a.path.cmp(&b.path))
+        });
+        self.flush(outputs);
+        self.print_last_separator();
+    }
+
+    fn print_last_separator(&self) {
+        // The flag is set once the first file with some output is flushed
+        if self.print_file_separator
+            && self.separator_after_last
+            && self.file_separator_printed.load(Ordering::Relaxed)
+        {
+            self.display.file_separator();
+        }
+    }
+
     fn canonicalize(&self, orig: &Path, resolved: &Path) -> anyhow::Result<PathBuf> {
         let cwd = env::current_dir()?;
         let parent = orig
             .parent()
             .ok_or_else(|| anyhow::Error::msg("no parent"))?;
-        env::set_current_dir(&parent)?;
+        env::set_current_dir(parent)?;
         let path = resolved
             .canonicalize()
             .map_err(|e| anyhow::Error::new(e).context(format!("cwd {}", parent.display())));
@@ -295,6 +1125,21 @@ impl Walker {
             );
             return;
         }
+        // Filters apply to the target of symlinks found by the walk, as to regular files
+        if let Some(root) = parents.first() {
+            if path.is_file()
+                && !self
+                    .file_filters
+                    .matches(path.to_str().unwrap(), root.to_str().unwrap())
+            {
+                debug!(
+                    "Skipping symlink '{}' -> '{}' not matching the file filters",
+                    orig.display(),
+                    path.display(),
+                );
+                return;
+            }
+        }
         self.walk_with_parents(&path, None, &{
             let mut parents = parents.to_owned();
             parents.push(path.clone());
@@ -318,13 +1163,36 @@ impl Walker {
         if file_type.is_dir() {
             self.walk_dir(path, parents);
         } else if file_type.is_file() {
-            Walker::grep(
+            if self.list_files {
+                self.list_file(path, meta.len() as usize);
+                return;
+            }
+            if let Some(mode) = self.filename_match {
+                if self.filename_match_type.accepts_files() {
+                    // The canonical path is matched as given when relative to the current directory
+                    let cwd = env::current_dir().and_then(|cwd| cwd.canonicalize());
+                    self.match_filename(path, &cwd.unwrap_or_default(), mode);
+                }
+                return;
+            }
+            let len = meta.len() as usize;
+            if self.sort_by.is_some() {
+                // Sorted along with the files of the other searched paths
+                self.grep_many(&[(path.to_path_buf(), len, meta.modified().ok())]);
+                return;
+            }
+            let count = Walker::grep(
                 self.grep.clone(),
                 Arc::new(path.to_path_buf()),
-                meta.len() as usize,
+                len,
                 self.matcher.clone(),
                 self.display.clone(),
+                self.reader_options.clone(),
             );
+            if self.stats.record(len, count) > 0 {
+                // The output is not buffered, only the trailing separator may follow
+                self.file_separator_printed.store(true, Ordering::Relaxed);
+            }
         } else if file_type.is_symlink() {
             if self.ignore_symlinks {
                 info!("Skipping symlink '{}'", path.display());
@@ -339,18 +1207,32 @@ impl Walker {
         }
     }
 
-    pub fn find_ignore_patterns_in_parents(path: &Path) -> Option<Patterns> {
-        if Self::contains_git_dir(path) {
+    /// Collects the `.gitignore` files of the parents up to the repository root,
+    /// the one of `path` itself is loaded by the walk before its entries are filtered.
+    /// Below one of `scan_roots` the scan stops at the nearest of them instead of at
+    /// the first parent containing `.git`.
+    pub fn find_ignore_patterns_in_parents(
+        path: &Path,
+        scan_roots: &[PathBuf],
+        cache: &mut IgnoreCache,
+    ) -> Option<Patterns> {
+        let bounded = scan_roots.iter().any(|root| path.starts_with(root));
+        if scan_roots.iter().any(|root| root == path) || !bounded && Self::contains_git_dir(path) {
             return None;
         }
         let mut patterns = Vec::new();
         let mut path = path.to_path_buf();
         while path.pop() {
-            if let Some(ignore_patterns) = Self::process_gitignore(&path) {
+            if let Some(ignore_patterns) = cache.get(&path) {
                 debug!("Found .gitignore in {}", path.display());
                 patterns.push(ignore_patterns);
             }
-            if Self::contains_git_dir(&path) {
+            let stop = if bounded {
+                scan_roots.contains(&path)
+            } else {
+                Self::contains_git_dir(&path)
+            };
+            if stop {
                 break;
             }
         }
@@ -364,7 +1246,299 @@ impl Walker {
         Some(ignore_patterns)
     }
 
-    pub fn walk(&self, path: &Path) {
+    /// Returns the stats, which include the walks of the other walkers sharing them.
+    /// With sorting the output is held back until `flush_sorted`.
+    pub fn walk(&self, path: &Path) -> Arc<Stats> {
         self.walk_with_parents(path, None, &[]);
+        if self.sort_by.is_none() {
+            self.print_last_separator();
+        }
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process, sync::Mutex};
+
+    use regex::Regex;
+
+    use super::*;
+    use crate::utils::display::{DisplayTerminal, Format};
+    use crate::utils::grep;
+    use crate::utils::matcher::Match;
+
+    #[test]
+    fn file_events() {
+        let root = env::temp_dir().join(format!("tgrep-walker-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        for (name, content) in [
+            ("a", "foo\nfoo\n"),
+            ("b", "bar\n"),
+            ("c", ""),
+            ("d", "\0foo"),
+        ] {
+            fs::write(root.join(name), content).unwrap();
+        }
+        let regexp = Regex::new("foo").unwrap();
+        let matcher: Matcher = Arc::new(Box::new(move |line: &str, _| {
+            regexp
+                .find(line)
+                .map(|m| vec![Match::new(m.start(), m.end())])
+        }));
+        let display = DisplayTerminal::new(
+            usize::MAX,
+            Format::PathOnly { colour: false },
+            Arc::new(Box::new(|path: &Path| path.to_str().unwrap().to_owned())),
+            Arc::new(BufferedWriter::new()),
+        );
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let on_file: OnFile = {
+            let events = events.clone();
+            Arc::new(move |event: FileEvent| {
+                let event = match event {
+                    FileEvent::Started(path) => (path.to_path_buf(), None),
+                    FileEvent::Finished(path, count) => (path.to_path_buf(), Some(count)),
+                };
+                events.lock().unwrap().push(event);
+            })
+        };
+        WalkerBuilder::new(grep::grep(), matcher, Arc::new(display))
+            .thread_pool(ThreadPool::new().unwrap())
+            .file_filters(Filters::new(&["*".to_owned()], false).unwrap())
+            .on_file(Some(on_file))
+            .build()
+            .walk(&root);
+
+        let events = events.lock().unwrap();
+        let events_of = |name: &str| -> Vec<_> {
+            events
+                .iter()
+                .filter(|(path, _)| *path == root.join(name))
+                .map(|(_, count)| *count)
+                .collect()
+        };
+        assert_eq!(vec![None, Some(Some(2))], events_of("a"));
+        assert_eq!(vec![None, Some(Some(0))], events_of("b"));
+        assert_eq!(vec![None, Some(Some(0))], events_of("c"));
+        // Binary files are skipped
+        assert_eq!(vec![None, Some(None)], events_of("d"));
+        assert_eq!(8, events.len());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn decisions() {
+        let root = env::temp_dir().join(format!("tgrep-decisions-{}", process::id()));
+        fs::create_dir_all(root.join("ignored")).unwrap();
+        for (name, content) in [
+            ("a.txt", "foo\n"),
+            ("b.rs", "foo\n"),
+            ("bin.txt", "\0foo"),
+            ("image.png", "foo\n"),
+            ("ignored.txt", "foo\n"),
+            ("ignored/a.txt", "foo\n"),
+        ] {
+            fs::write(root.join(name), content).unwrap();
+        }
+        let matcher: Matcher = Arc::new(Box::new(|_: &str, _| None));
+        let display = DisplayTerminal::new(
+            usize::MAX,
+            Format::PathOnly { colour: false },
+            Arc::new(Box::new(|path: &Path| path.to_str().unwrap().to_owned())),
+            Arc::new(BufferedWriter::new()),
+        );
+        let decisions = Arc::new(Mutex::new(Vec::new()));
+        let on_considered: OnConsidered = {
+            let decisions = decisions.clone();
+            let root = root.clone();
+            Arc::new(move |path: &Path, decision| {
+                decisions
+                    .lock()
+                    .unwrap()
+                    .push((path.strip_prefix(&root).unwrap().to_owned(), decision));
+            })
+        };
+        let root_str = root.to_str().unwrap();
+        let ignored = ["ignored.txt".to_owned(), "ignored/".to_owned()];
+        WalkerBuilder::new(grep::grep(), matcher, Arc::new(display))
+            .thread_pool(ThreadPool::new().unwrap())
+            .force_ignore_patterns(Patterns::new(root_str, &ignored))
+            .file_filters(Filters::new(&["*.txt".to_owned(), "*.png".to_owned()], false).unwrap())
+            .skip_binary_ext(true, &[])
+            .on_considered(Some(on_considered))
+            .build()
+            .walk(&root);
+
+        let mut decisions = decisions.lock().unwrap().clone();
+        decisions.sort_by(|a, b| a.0.cmp(&b.0));
+        let decisions: Vec<_> = decisions
+            .iter()
+            .map(|(path, decision)| (path.to_str().unwrap(), *decision))
+            .collect();
+        assert_eq!(
+            vec![
+                ("a.txt", Decision::Searched),
+                ("b.rs", Decision::Filtered),
+                ("bin.txt", Decision::Binary),
+                ("ignored", Decision::Ignored),
+                ("ignored.txt", Decision::Ignored),
+                ("image.png", Decision::Binary),
+            ],
+            decisions
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn no_mmap() {
+        let path = env::temp_dir().join(format!("tgrep-no-mmap-{}", process::id()));
+        fs::write(&path, "foo\nbar\n").unwrap();
+        let regexp = Regex::new("foo|bar").unwrap();
+        let matcher: Matcher = Arc::new(Box::new(move |line: &str, _| {
+            regexp
+                .find(line)
+                .map(|m| vec![Match::new(m.start(), m.end())])
+        }));
+        let display: Arc<dyn Display> = Arc::new(DisplayTerminal::new(
+            usize::MAX,
+            Format::PathOnly { colour: false },
+            Arc::new(Box::new(|path: &Path| path.to_str().unwrap().to_owned())),
+            Arc::new(BufferedWriter::new()),
+        ));
+        // Stale size, as of virtual files: the mapping is truncated while reads see everything
+        let read = |options: ReaderOptions| {
+            let entry = Arc::new(path.clone());
+            Walker::read(
+                grep::grep(),
+                entry,
+                4,
+                matcher.clone(),
+                display.clone(),
+                options,
+            )
+        };
+        assert_eq!(Some(1), read(ReaderOptions::default()));
+        let glob = path.file_name().unwrap().to_str().unwrap().to_owned();
+        let options = ReaderOptions {
+            no_mmap: Some(Arc::new(Filters::new(&[glob], false).unwrap())),
+            ..Default::default()
+        };
+        assert_eq!(Some(2), read(options));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ignore_cache() {
+        let root = env::temp_dir().join(format!("tgrep-ignore-cache-{}", process::id()));
+        for dir in [".git", "a", "b", "c"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join(GIT_IGNORE), "*.log\n").unwrap();
+        let root = root.canonicalize().unwrap();
+        let mut cache = IgnoreCache::new(true);
+        let excluded = |patterns: Option<Patterns>, dir: &str| {
+            let path = root.join(dir).join("x.log");
+            patterns.unwrap().is_excluded(path.to_str().unwrap(), false)
+        };
+        let patterns = Walker::find_ignore_patterns_in_parents(&root.join("a"), &[], &mut cache);
+        assert!(excluded(patterns, "a"));
+        // Sibling start paths reuse the parsed root `.gitignore`
+        fs::write(root.join(GIT_IGNORE), "").unwrap();
+        for dir in ["b", "c"] {
+            let patterns =
+                Walker::find_ignore_patterns_in_parents(&root.join(dir), &[], &mut cache);
+            assert!(excluded(patterns, dir));
+        }
+        assert_eq!(1, cache.patterns.len());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ignore_scan_root() {
+        let root = env::temp_dir().join(format!("tgrep-ignore-scan-root-{}", process::id()));
+        fs::create_dir_all(root.join("outer/.git")).unwrap();
+        fs::create_dir_all(root.join("outer/inner/a")).unwrap();
+        fs::write(root.join(GIT_IGNORE), "*.log\n").unwrap();
+        fs::write(root.join("outer").join(GIT_IGNORE), "*.tmp\n").unwrap();
+        fs::write(root.join("outer/inner").join(GIT_IGNORE), "*.bak\n").unwrap();
+        let root = root.canonicalize().unwrap();
+        let start = root.join("outer/inner/a");
+        let excluded = |patterns: &Option<Patterns>, name: &str| {
+            let path = start.join(name);
+            patterns
+                .as_ref()
+                .unwrap()
+                .is_excluded(path.to_str().unwrap(), false)
+        };
+
+        // The `.git` of `outer` ends the scan
+        let patterns =
+            Walker::find_ignore_patterns_in_parents(&start, &[], &mut IgnoreCache::new(false));
+        assert!(excluded(&patterns, "x.bak"));
+        assert!(excluded(&patterns, "x.tmp"));
+        assert!(!excluded(&patterns, "x.log"));
+
+        // An explicit root above it overrides the heuristic
+        let scan_roots = [root.clone()];
+        let patterns = Walker::find_ignore_patterns_in_parents(
+            &start,
+            &scan_roots,
+            &mut IgnoreCache::new(false),
+        );
+        assert!(excluded(&patterns, "x.log"));
+
+        // As does one below it
+        let scan_roots = [root.join("outer/inner")];
+        let patterns = Walker::find_ignore_patterns_in_parents(
+            &start,
+            &scan_roots,
+            &mut IgnoreCache::new(false),
+        );
+        assert!(excluded(&patterns, "x.bak"));
+        assert!(!excluded(&patterns, "x.tmp"));
+
+        // Paths outside of the roots keep the heuristic
+        let scan_roots = [root.join("elsewhere")];
+        let patterns = Walker::find_ignore_patterns_in_parents(
+            &start,
+            &scan_roots,
+            &mut IgnoreCache::new(false),
+        );
+        assert!(excluded(&patterns, "x.tmp"));
+        assert!(!excluded(&patterns, "x.log"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn shared_thread_pool() {
+        let root = env::temp_dir().join(format!("tgrep-walker-pool-{}", process::id()));
+        for (dir, files) in [("a", 3), ("b", 5)] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            for i in 0..files {
+                fs::write(root.join(dir).join(i.to_string()), "foo\n").unwrap();
+            }
+        }
+        let matcher: Matcher = Arc::new(Box::new(|line: &str, _| {
+            line.find("foo").map(|pos| vec![Match::new(pos, pos + 3)])
+        }));
+        let display: Arc<dyn Display> = Arc::new(DisplayTerminal::new(
+            usize::MAX,
+            Format::PathOnly { colour: false },
+            Arc::new(Box::new(|path: &Path| path.to_str().unwrap().to_owned())),
+            Arc::new(BufferedWriter::new()),
+        ));
+        let tpool = ThreadPool::new().unwrap();
+        for (dir, files) in [("a", 3), ("b", 5)] {
+            let stats = WalkerBuilder::new(grep::grep(), matcher.clone(), display.clone())
+                .thread_pool(tpool.clone())
+                .file_filters(Filters::new(&["*".to_owned()], false).unwrap())
+                .stats(Arc::new(Stats::default()))
+                .build()
+                .walk(&root.join(dir));
+            assert_eq!(files, stats.files_searched());
+            assert_eq!(files, stats.matches());
+        }
+        fs::remove_dir_all(&root).unwrap();
     }
 }
@@ -2,6 +2,11 @@ use std::{
     cell::RefCell,
     sync::{Arc, Mutex},
 };
+#[cfg(unix)]
+use std::{io::Write, os::unix::net::UnixStream, path::Path};
+
+#[cfg(unix)]
+use log::error;
 
 pub trait Writer: Send + Sync {
     fn write(&self, content: &str);
@@ -29,6 +34,33 @@ impl Writer for StdoutWriter {
     }
 }
 
+/// Writes newline-delimited content to a Unix domain socket
+#[cfg(unix)]
+pub struct SocketWriter {
+    stream: Mutex<UnixStream>,
+}
+
+#[cfg(unix)]
+impl SocketWriter {
+    pub fn connect(path: &Path) -> anyhow::Result<Self> {
+        let stream = UnixStream::connect(path)
+            .map_err(|e| anyhow::anyhow!("failed to connect to '{}': {}", path.display(), e))?;
+        Ok(SocketWriter {
+            stream: Mutex::new(stream),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Writer for SocketWriter {
+    fn write(&self, content: &str) {
+        let mut stream = self.stream.lock().unwrap();
+        if let Err(e) = writeln!(stream, "{}", content) {
+            error!("Failed to write to socket: {}", e);
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct BufferedWriter {
     lines: Arc<Mutex<RefCell<Vec<String>>>>,
@@ -51,7 +83,7 @@ impl BufferedWriter {
     }
 
     pub fn has_some(&self) -> bool {
-        self.lines.lock().unwrap().borrow().len() > 0
+        !self.lines.lock().unwrap().borrow().is_empty()
     }
 }
 
@@ -0,0 +1,1867 @@
+use std::{
+    env, fs,
+    io::Write,
+    path::PathBuf,
+    process::{self, Command, Output, Stdio},
+    time::{Duration, SystemTime},
+};
+
+fn tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root = env::temp_dir().join(format!("tgrep-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for (path, content) in files {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    root
+}
+
+fn tgrep(args: &[&str], stdin: Option<&str>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tgrep"))
+        .arg("--no-colour")
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    if let Some(stdin) = stdin {
+        // tgrep may exit before reading its input, e.g. on conflicting flags
+        let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+    }
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> Vec<&str> {
+    std::str::from_utf8(&output.stdout)
+        .unwrap()
+        .lines()
+        .collect()
+}
+
+#[test]
+fn stdin_with_bad_path() {
+    let output = tgrep(&["foo", "/nonexistent/tgrep"], Some("foo\nbar\n"));
+    assert_eq!(vec!["<stdin>:1: foo"], stdout(&output));
+    assert!(!output.status.success());
+}
+
+#[test]
+fn max_matches_total() {
+    let output = tgrep(
+        &["--max-matches-total", "2", "--threads", "1", "foo"],
+        Some("foo\nbar\nfoo\nfoo\n"),
+    );
+    assert_eq!(vec!["1: foo", "3: foo"], stdout(&output));
+    // Matches are counted, not lines
+    let input = Some("foo foo\nfoo\n");
+    let output = tgrep(&["--max-matches-total", "2", "foo"], input);
+    assert_eq!(vec!["1: foo foo"], stdout(&output));
+    let output = tgrep(&["--max-matches-total", "1", "-o", "foo"], input);
+    assert_eq!(vec!["1: foo"], stdout(&output));
+    let output = tgrep(&["--max-matches-total", "1", "-c", "foo"], input);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn zero_threads() {
+    let output = tgrep(&["--threads", "0", "foo"], Some("foo\n"));
+    assert!(!output.status.success());
+    assert_eq!(Some(1), output.status.code());
+}
+
+#[test]
+fn filename_match() {
+    let root = tree(
+        "filename-match",
+        &[
+            ("foo/bar.rs", "baz"),
+            ("foo/baz.rs", "bar"),
+            ("qux.txt", ""),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["--filename-match", "foo/", root_str], None);
+    assert_eq!(
+        vec![
+            format!("{}/foo/bar.rs", root_str),
+            format!("{}/foo/baz.rs", root_str)
+        ],
+        stdout(&output)
+    );
+    let output = tgrep(&["--filename-only", "^ba[rz]", root_str], None);
+    assert_eq!(2, stdout(&output).len());
+    let output = tgrep(&["--filename-only", "foo", root_str], None);
+    assert!(stdout(&output).is_empty());
+    // A searched file is matched by its path as well
+    let file = root.join("foo/bar.rs");
+    let file = file.to_str().unwrap();
+    let output = tgrep(&["--filename-match", "foo/", file], None);
+    assert_eq!(vec![file], stdout(&output));
+    let output = tgrep(&["--filename-only", "foo/", file], None);
+    assert!(stdout(&output).is_empty());
+    // Flags about the file contents are rejected rather than ignored
+    for flags in [
+        &["-c"][..],
+        &["--count-matches"],
+        &["-A", "1"],
+        &["-B", "1"],
+        &["--replace", "x"],
+    ] {
+        for mode in ["--filename-match", "--filename-only"] {
+            let args = [&[mode, "b"], flags, &[root_str]].concat();
+            assert!(!tgrep(&args, None).status.success(), "{:?}", args);
+        }
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn sort_by_count() {
+    let root = tree(
+        "sort-count",
+        &[
+            ("a", "foo\n"),
+            ("b", "foo\nfoo\nfoo\n"),
+            ("c", "foo\nfoo\n"),
+            ("d", "foo\nfoo\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["-c", "--sort", "count", "foo", root_str], None);
+    assert_eq!(
+        vec![
+            format!("{}/b: 3", root_str),
+            format!("{}/c: 2", root_str),
+            format!("{}/d: 2", root_str),
+            format!("{}/a: 1", root_str),
+        ],
+        stdout(&output)
+    );
+    let output = tgrep(&["-c", "--sortr", "count", "foo", root_str], None);
+    assert_eq!(
+        vec![
+            format!("{}/a: 1", root_str),
+            format!("{}/c: 2", root_str),
+            format!("{}/d: 2", root_str),
+            format!("{}/b: 3", root_str),
+        ],
+        stdout(&output)
+    );
+    // Sorted across the searched paths
+    let (a, b) = (root.join("a"), root.join("b"));
+    let paths = [a.to_str().unwrap(), b.to_str().unwrap()];
+    let output = tgrep(&["-c", "--sort", "count", "foo", paths[0], paths[1]], None);
+    assert_eq!(
+        vec![format!("{}: 3", paths[1]), format!("{}: 1", paths[0])],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn sort_by_modified() {
+    let root = tree(
+        "sort-modified",
+        &[("a", "foo\n"), ("b", "foo\n"), ("c", "foo\n")],
+    );
+    let now = SystemTime::now();
+    for (name, age) in [("a", 10), ("b", 30), ("c", 20)] {
+        fs::File::options()
+            .write(true)
+            .open(root.join(name))
+            .unwrap()
+            .set_modified(now - Duration::from_secs(age))
+            .unwrap();
+    }
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["-l", "--sort", "modified", "foo", root_str], None);
+    assert_eq!(
+        vec![
+            format!("{}/b", root_str),
+            format!("{}/c", root_str),
+            format!("{}/a", root_str),
+        ],
+        stdout(&output)
+    );
+    let output = tgrep(&["-l", "--sortr", "modified", "foo", root_str], None);
+    assert_eq!(
+        vec![
+            format!("{}/a", root_str),
+            format!("{}/c", root_str),
+            format!("{}/b", root_str),
+        ],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn binary() {
+    let root = tree(
+        "binary",
+        &[("bin", "foo\x00\x01\x02\nfoo\n"), ("text", "foo\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["foo", root_str], None);
+    assert_eq!(vec![format!("{}/text:1: foo", root_str)], stdout(&output));
+    let output = tgrep(&["--binary", "foo", root_str], None);
+    assert_eq!(
+        vec![
+            format!("Binary file {}/bin matches", root_str),
+            format!("{}/text:1: foo", root_str),
+        ],
+        stdout(&output)
+    );
+    let output = tgrep(&["--binary", "-l", "foo", root_str], None);
+    assert_eq!(
+        vec![format!("{}/bin", root_str), format!("{}/text", root_str)],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn skip_binary_ext() {
+    // Text content, so that the files match if they are read at all
+    let root = tree(
+        "skip-binary-ext",
+        &[
+            ("image.PNG", "foo\n"),
+            ("blob.dat", "foo\n"),
+            ("text", "foo\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["-l", "foo", root_str], None);
+    assert_eq!(3, stdout(&output).len());
+    let output = tgrep(&["--skip-binary-ext", "-l", "foo", root_str], None);
+    assert_eq!(
+        vec![
+            format!("{}/blob.dat", root_str),
+            format!("{}/text", root_str)
+        ],
+        stdout(&output)
+    );
+    let output = tgrep(
+        &[
+            "--skip-binary-ext",
+            "--binary-ext",
+            ".dat",
+            "-l",
+            "foo",
+            root_str,
+        ],
+        None,
+    );
+    assert_eq!(vec![format!("{}/text", root_str)], stdout(&output));
+    let output = tgrep(&["--binary", "--skip-binary-ext", "foo", root_str], None);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn hidden_and_git_dir() {
+    let root = tree(
+        "hidden",
+        &[
+            (".git/objects/aa", "foo\n"),
+            (".hidden", "foo\n"),
+            (".gitignore", "ignored\n"),
+            ("ignored", "foo\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["-l", "foo", root_str], None);
+    assert_eq!(vec![format!("{}/.hidden", root_str)], stdout(&output));
+    let output = tgrep(&["-l", "--no-ignore", "foo", root_str], None);
+    assert_eq!(
+        vec![
+            format!("{}/.git/objects/aa", root_str),
+            format!("{}/.hidden", root_str),
+            format!("{}/ignored", root_str),
+        ],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn conflicting_flags() {
+    for (flags, message) in [
+        (&["-L", "-v"][..], "-L and -v"),
+        (&["-L", "-l"][..], "-L and -l"),
+        (&["-L", "-o"][..], "-L and -o"),
+        (&["-L", "-c"][..], "-L and -c"),
+        (&["-L", "-A", "1"][..], "-L and -A/-B"),
+        (&["-l", "-o"][..], "-l and -o"),
+        (&["-l", "-c"][..], "-l and -c"),
+        (&["-l", "-B", "1"][..], "-l and -A/-B"),
+        (&["-c", "-o", "-v"][..], "-c -o and -v"),
+        (&["-c", "-A", "1"][..], "-c and -A/-B"),
+    ] {
+        let mut args = flags.to_vec();
+        args.push("foo");
+        let output = tgrep(&args, Some("foo\n"));
+        assert!(!output.status.success(), "{:?}", flags);
+        assert!(stdout(&output).is_empty(), "{:?}", flags);
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert!(
+            stderr.contains(&format!("incompatible flags: {}", message)),
+            "{:?}: {}",
+            flags,
+            stderr
+        );
+    }
+    let output = tgrep(&["-o", "-c", "foo"], Some("foo foo\n"));
+    assert!(output.status.success());
+}
+
+#[test]
+fn count_inverted() {
+    let input = Some("foo\nbar\nfoo bar\nbaz\n");
+    assert_eq!(vec!["2"], stdout(&tgrep(&["-c", "foo"], input)));
+    assert_eq!(vec!["2"], stdout(&tgrep(&["-c", "-v", "foo"], input)));
+    assert_eq!(vec!["1"], stdout(&tgrep(&["-c", "-v", "ba"], input)));
+    assert!(stdout(&tgrep(&["-c", "-v", "."], input)).is_empty());
+}
+
+#[test]
+fn empty_files() {
+    let root = tree(
+        "empty",
+        &[("empty", ""), ("foo", "foo\n"), ("bar", "bar\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let sorted = |output: &Output| {
+        let mut lines: Vec<String> = stdout(output).iter().map(|l| l.to_string()).collect();
+        lines.sort();
+        lines
+    };
+    let output = tgrep(&["-L", "foo", root_str], None);
+    assert_eq!(
+        vec![format!("{}/bar", root_str), format!("{}/empty", root_str)],
+        sorted(&output)
+    );
+    let output = tgrep(&["-l", "foo", root_str], None);
+    assert_eq!(vec![format!("{}/foo", root_str)], sorted(&output));
+    let output = tgrep(&["-c", "foo", root_str], None);
+    assert_eq!(vec![format!("{}/foo: 1", root_str)], sorted(&output));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn engine() {
+    let input = Some("a.c\nabc\n");
+    let output = tgrep(&["a.c"], input);
+    assert_eq!(vec!["1: a.c", "2: abc"], stdout(&output));
+    let output = tgrep(&["--engine", "literal", "a.c"], input);
+    assert_eq!(vec!["1: a.c"], stdout(&output));
+    let output = tgrep(&["--engine", "auto", "a.c"], input);
+    assert_eq!(vec!["1: a.c", "2: abc"], stdout(&output));
+    let output = tgrep(&["--engine", "auto", "abc"], input);
+    assert_eq!(vec!["2: abc"], stdout(&output));
+}
+
+#[test]
+fn stdin_path() {
+    let input = Some("foo\nbar\n");
+    assert_eq!(vec!["1: foo"], stdout(&tgrep(&["foo"], input)));
+    assert_eq!(
+        vec!["<stdin>:1: foo"],
+        stdout(&tgrep(&["-H", "foo"], input))
+    );
+    assert_eq!(vec!["foo"], stdout(&tgrep(&["-h", "foo"], input)));
+}
+
+#[test]
+fn replace() {
+    let root = tree(
+        "replace",
+        &[
+            ("a", "foo bar\nbaz\n"),
+            ("b", "qux\r\nfoo\r\n"),
+            ("c", "none\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(
+        &["-H", "--replace", "<$0>", "foo", &format!("{}/a", root_str)],
+        None,
+    );
+    assert_eq!(
+        vec![format!("{}/a:1: <foo> bar", root_str)],
+        stdout(&output)
+    );
+    assert_eq!(
+        "foo bar\nbaz\n",
+        fs::read_to_string(root.join("a")).unwrap()
+    );
+
+    let output = tgrep(&["--replace", "<$0>", "--write", "foo", root_str], None);
+    let mut modified = stdout(&output);
+    modified.sort();
+    assert_eq!(
+        vec![format!("{}/a", root_str), format!("{}/b", root_str)],
+        modified
+    );
+    assert_eq!(
+        "2 files modified\n",
+        std::str::from_utf8(&output.stderr).unwrap()
+    );
+    assert_eq!(
+        "<foo> bar\nbaz\n",
+        fs::read_to_string(root.join("a")).unwrap()
+    );
+    assert_eq!(
+        "qux\r\n<foo>\r\n",
+        fs::read_to_string(root.join("b")).unwrap()
+    );
+    assert_eq!("none\n", fs::read_to_string(root.join("c")).unwrap());
+
+    let output = tgrep(&["--replace", "x", "--write", "foo"], Some("foo\n"));
+    assert!(!output.status.success());
+
+    // The file would be written back as UTF-8
+    fs::write(root.join("d"), b"caf\xe9 foo\n").unwrap();
+    let d = root.join("d");
+    let args = ["--encoding", "latin1", "--replace", "bar", "--write", "foo"];
+    let output = tgrep(&[&args[..], &[d.to_str().unwrap()]].concat(), None);
+    assert!(!output.status.success());
+    assert_eq!(b"caf\xe9 foo\n", &fs::read(&d).unwrap()[..]);
+    // Nor is a file that is not valid UTF-8 rewritten
+    let output = tgrep(
+        &["--replace", "bar", "--write", "foo", d.to_str().unwrap()],
+        None,
+    );
+    assert!(stdout(&output).is_empty());
+    assert!(std::str::from_utf8(&output.stderr)
+        .unwrap()
+        .contains("Not rewriting"));
+    assert_eq!(b"caf\xe9 foo\n", &fs::read(&d).unwrap()[..]);
+
+    // Only the matches the limits let through are replaced
+    fs::write(root.join("e"), "foo foo\nfoo\n").unwrap();
+    let e = root.join("e");
+    let args = [
+        "--max-matches-total",
+        "2",
+        "--replace",
+        "x",
+        "--write",
+        "foo",
+    ];
+    let output = tgrep(&[&args[..], &[e.to_str().unwrap()]].concat(), None);
+    assert_eq!(vec![e.to_str().unwrap()], stdout(&output));
+    assert_eq!("x x\nfoo\n", fs::read_to_string(&e).unwrap());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn replace_named_groups() {
+    let pattern = r"(?P<year>\d{4})-(?P<month>\d{2})";
+    let output = tgrep(
+        &["--replace", "${month}/${year}", pattern],
+        Some(
+            "since 2021-03
+none
+",
+        ),
+    );
+    assert_eq!(vec!["1: since 03/2021"], stdout(&output));
+    let output = tgrep(
+        &["--replace", "${day}", pattern],
+        Some(
+            "2021-03
+",
+        ),
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no capture group 'day'"));
+}
+
+#[test]
+fn files() {
+    let root = tree("files", &[("a", "foo\n"), ("e", ""), ("d/bin", "x\0y")]);
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["--files", root_str], None);
+    assert_eq!(
+        vec![format!("{}/a", root_str), format!("{}/e", root_str)],
+        stdout(&output)
+    );
+    let output = tgrep(&["--files", "--json", "--binary", root_str], None);
+    assert_eq!(
+        vec![
+            format!(
+                r#"{{"mmap":true,"path":"{}/d/bin","size":3,"type":"binary"}}"#,
+                root_str
+            ),
+            format!(
+                r#"{{"mmap":true,"path":"{}/a","size":4,"type":"text"}}"#,
+                root_str
+            ),
+            format!(
+                r#"{{"mmap":false,"path":"{}/e","size":0,"type":"empty"}}"#,
+                root_str
+            ),
+        ],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn capture() {
+    let input = Some("a=1 b=2\nc\nd=\n");
+    let output = tgrep(&["-o", "--capture", "1", r"(\w)=(\d)?"], input);
+    assert_eq!(vec!["1: a", "1: b", "3: d"], stdout(&output));
+    let output = tgrep(&["-o", "--capture", "2", r"(\w)=(\d)?"], input);
+    assert_eq!(vec!["1: 1", "1: 2"], stdout(&output));
+    let output = tgrep(&["-o", "--capture", "3", r"(\w)=(\d)?"], input);
+    assert!(!output.status.success());
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains("no capture group 3"), "{}", stderr);
+}
+
+#[test]
+fn word_regexp() {
+    let input = Some("café\ncaf\ncafe\n");
+    assert_eq!(vec!["2: caf"], stdout(&tgrep(&["-w", "caf"], input)));
+    assert_eq!(
+        vec!["1: café", "2: caf"],
+        stdout(&tgrep(&["-w", "--ascii-word", "caf"], input))
+    );
+    assert_eq!(
+        vec!["1: café", "2: caf", "3: cafe"],
+        stdout(&tgrep(&["caf"], input))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn socket() {
+    use std::{io::Read, os::unix::net::UnixListener};
+
+    let path = env::temp_dir().join(format!("tgrep-socket-{}", process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let output = tgrep(
+        &["--socket", path.to_str().unwrap(), "o"],
+        Some("foo\nbar\n"),
+    );
+    assert!(output.status.success());
+    assert!(stdout(&output).is_empty());
+    let mut received = String::new();
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.read_to_string(&mut received).unwrap();
+    assert_eq!(
+        "{\"line\":\"foo\",\"lno\":1,\"matches\":[[1,2],[2,3]],\"path\":\"<stdin>\"}\n",
+        received
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn unique() {
+    let root = tree(
+        "unique",
+        &[("a", "foo = 1\nfoo = 2\n"), ("b", "foo = 1\nfoo = 3\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["--unique", "-h", "foo", root_str], None);
+    let mut lines = stdout(&output);
+    lines.sort();
+    assert_eq!(vec!["foo = 1", "foo = 2", "foo = 3"], lines);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn prefilter() {
+    let root = tree("prefilter", &[("a", "bar\r\nbaz\r\nfoo\r\n")]);
+    let path = root.join("a");
+    let path = path.to_str().unwrap();
+    let output = tgrep(&["^foo$", path], None);
+    assert_eq!(vec!["3: foo"], stdout(&output));
+    let output = tgrep(&["-v", "foo", path], None);
+    assert_eq!(vec!["1: bar", "2: baz"], stdout(&output));
+    let output = tgrep(&["-L", "^foo", path], None);
+    assert!(stdout(&output).is_empty());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn line_number_width() {
+    let input: String = (1..=10).map(|i| format!("{}\n", i)).collect();
+    let output = tgrep(&["--line-number-width", "3", "^(1|10)$"], Some(&input));
+    assert_eq!(vec!["  1: 1", " 10: 10"], stdout(&output));
+}
+
+#[test]
+fn stats() {
+    let root = tree(
+        "stats",
+        &[
+            ("a", "foo\n"),
+            ("bin", "foo\0"),
+            ("ignored", "foo\n"),
+            (".gitignore", "ignored\n"),
+        ],
+    );
+    let output = tgrep(&["--stats", "foo", root.to_str().unwrap()], None);
+    assert_eq!(1, stdout(&output).len());
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("1 files searched, 2 skipped, 4 bytes scanned in "),
+        "{}",
+        stderr
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn inline_case_flags() {
+    let input = Some("Foobar\nFooBAR\nfoobar\nFOOBAR\n");
+    let output = tgrep(&["-i", "(?-i:Foo)bar"], input);
+    assert_eq!(vec!["1: Foobar", "2: FooBAR"], stdout(&output));
+    let output = tgrep(&["(?i:foo)bar"], input);
+    assert_eq!(vec!["1: Foobar", "3: foobar"], stdout(&output));
+}
+
+#[test]
+fn section_regex() {
+    let input = Some("name = top\n[core]\nname = a\n; [core] in a comment\n[user]\nname = b\n");
+    let output = tgrep(&["--section-regex", "^\\[core\\]", "name"], input);
+    assert_eq!(vec!["3: name = a"], stdout(&output));
+    let output = tgrep(
+        &[
+            "--section-regex",
+            "user",
+            "--section-header",
+            "^\\[",
+            "name",
+        ],
+        input,
+    );
+    assert_eq!(vec!["6: name = b"], stdout(&output));
+}
+
+#[test]
+fn mixed_paths() {
+    let root = tree(
+        "mixed_paths",
+        &[
+            ("a.txt", "foo\n"),
+            ("dir/b.txt", "foo\n"),
+            ("dir/sub/c.txt", "foo\n"),
+        ],
+    );
+    let file = root.join("a.txt");
+    let dir = root.join("dir");
+    let (file, dir) = (file.to_str().unwrap(), dir.to_str().unwrap());
+    let with_slash = format!("{}/", dir);
+    for dir in [dir, &with_slash] {
+        let output = tgrep(&["foo", file, dir], None);
+        let mut lines = stdout(&output);
+        lines.sort();
+        assert_eq!(
+            vec![
+                format!("{}:1: foo", file),
+                format!("{}/b.txt:1: foo", with_slash.trim_end_matches('/')),
+                format!("{}/sub/c.txt:1: foo", with_slash.trim_end_matches('/')),
+            ],
+            lines
+        );
+    }
+    #[cfg(unix)]
+    {
+        let link = root.join("link");
+        std::os::unix::fs::symlink(root.join("dir/sub"), &link).unwrap();
+        let output = tgrep(&["foo", link.to_str().unwrap()], None);
+        assert_eq!(
+            vec![format!("{}/c.txt:1: foo", link.to_str().unwrap())],
+            stdout(&output)
+        );
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn single_file() {
+    let root = tree("single_file", &[("a", "foo\n"), ("dir/b", "foo\n")]);
+    let file = root.join("a");
+    let dir = root.join("dir");
+    let (file, dir) = (file.to_str().unwrap(), dir.to_str().unwrap());
+    let output = tgrep(&["foo", file], None);
+    assert_eq!(vec!["1: foo"], stdout(&output));
+    let output = tgrep(&["-H", "foo", file], None);
+    assert_eq!(vec![format!("{}:1: foo", file)], stdout(&output));
+    let output = tgrep(&["-l", "foo", file], None);
+    assert_eq!(vec![file], stdout(&output));
+    // A directory is searched recursively, its files are named
+    let output = tgrep(&["foo", dir], None);
+    assert_eq!(vec![format!("{}/b:1: foo", dir)], stdout(&output));
+    let output = tgrep(&["foo", file], Some("foo\n"));
+    assert_eq!(
+        vec![format!("{}:1: foo", file), "<stdin>:1: foo".to_owned()],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn git_diff() {
+    let root = tree(
+        "git_diff",
+        &[
+            ("a", "foo 1\nbar\nfoo 3\n"),
+            ("b", "foo\n"),
+            (".gitignore", "ignored\n"),
+        ],
+    );
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&root)
+            .args(["-c", "user.name=tgrep", "-c", "user.email=tgrep@localhost"])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "init"]);
+    fs::write(root.join("a"), "foo 1\nfoo 2\nfoo 3\nfoo 4\n").unwrap();
+    fs::write(root.join("new"), "foo\n").unwrap();
+    fs::write(root.join("ignored"), "foo\n").unwrap();
+
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["--git-diff", "foo", root_str], None);
+    assert_eq!(
+        vec![
+            format!("{}/a:2: foo 2", root_str),
+            format!("{}/a:4: foo 4", root_str)
+        ],
+        stdout(&output)
+    );
+    let output = tgrep(&["--git-diff=all", "foo", root_str], None);
+    let mut lines = stdout(&output);
+    lines.sort();
+    assert_eq!(
+        vec![
+            format!("{}/a:2: foo 2", root_str),
+            format!("{}/a:4: foo 4", root_str),
+            format!("{}/new:1: foo", root_str),
+        ],
+        lines
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn separator_after_last() {
+    let root = tree(
+        "separator_after_last",
+        &[("a", "foo\n"), ("b", "foo\n"), ("c", "bar\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let file = root.join("a");
+    let output = tgrep(
+        &[
+            "-A",
+            "1",
+            "--separator-after-last",
+            "foo",
+            file.to_str().unwrap(),
+        ],
+        None,
+    );
+    assert_eq!(vec!["1: foo", "--"], stdout(&output));
+    let output = tgrep(
+        &["-A", "1", "--separator-after-last", "foo", root_str],
+        None,
+    );
+    assert_eq!(
+        vec![
+            format!("{}/a:1: foo", root_str),
+            "--".to_owned(),
+            format!("{}/b:1: foo", root_str),
+            "--".to_owned(),
+        ],
+        stdout(&output)
+    );
+    let output = tgrep(&["-A", "1", "foo", root_str], None);
+    assert_eq!(3, stdout(&output).len());
+    // Nothing is printed without matches
+    let output = tgrep(
+        &["-A", "1", "--separator-after-last", "qux", root_str],
+        None,
+    );
+    assert!(stdout(&output).is_empty());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn line_buffered() {
+    let root = tree(
+        "line_buffered",
+        &[("a", "foo 1\nfoo 2\n"), ("b", "foo 3\n"), ("c", "foo 4\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(
+        &["--line-buffered", "--threads", "1", "foo", root_str],
+        None,
+    );
+    let mut lines = stdout(&output);
+    lines.sort();
+    assert_eq!(
+        vec![
+            format!("{}/a:1: foo 1", root_str),
+            format!("{}/a:2: foo 2", root_str),
+            format!("{}/b:1: foo 3", root_str),
+            format!("{}/c:1: foo 4", root_str),
+        ],
+        lines
+    );
+    let output = tgrep(
+        &["--line-buffered", "--sort", "count", "foo", root_str],
+        None,
+    );
+    assert!(!output.status.success());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn root_gitignore() {
+    let root = tree(
+        "root_gitignore",
+        &[
+            // Above the repository, must not apply
+            (".gitignore", "a\n"),
+            ("repo/.git/HEAD", "\n"),
+            ("repo/.gitignore", "/top\n"),
+            ("repo/a", "foo\n"),
+            ("repo/top", "foo\n"),
+            ("repo/sub/top", "foo\n"),
+        ],
+    );
+    let repo = root.join("repo");
+    let repo = repo.to_str().unwrap();
+    let output = tgrep(&["-l", "foo", repo], None);
+    let mut files = stdout(&output);
+    files.sort();
+    assert_eq!(
+        vec![format!("{}/a", repo), format!("{}/sub/top", repo)],
+        files
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn json_file_events() {
+    let root = tree(
+        "json_file_events",
+        &[
+            ("a", "foo\nbar\nfoo\n"),
+            ("b", "foo\n"),
+            ("c", "bar\n"),
+            ("d", "foo\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["--json", "foo", root_str], None);
+    let records: Vec<serde_json::Value> = stdout(&output)
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // Every file is reported as a contiguous begin, matches, end sequence
+    let mut files = Vec::new();
+    let mut records = records.iter();
+    while let Some(begin) = records.next() {
+        assert_eq!("begin", begin["type"]);
+        let path = begin["path"].as_str().unwrap();
+        let mut matches = 0;
+        let end = loop {
+            let record = records.next().unwrap();
+            if record.get("type").is_some() {
+                break record;
+            }
+            assert_eq!(path, record["path"]);
+            matches += 1;
+        };
+        assert_eq!("end", end["type"]);
+        assert_eq!(path, end["path"]);
+        assert_eq!(matches, end["matches"]);
+        files.push((path.to_owned(), matches, end["bytes"].as_u64().unwrap()));
+    }
+    files.sort();
+    assert_eq!(
+        vec![
+            (format!("{}/a", root_str), 2, 12),
+            (format!("{}/b", root_str), 1, 4),
+            (format!("{}/d", root_str), 1, 4),
+        ],
+        files
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn json_counts_and_paths() {
+    let root = tree("json_counts", &[("a", "foo\nfoo foo\nbar\n")]);
+    let a = root.join("a");
+    let a = a.to_str().unwrap();
+    let records = |flags: &[&str]| -> Vec<serde_json::Value> {
+        let output = tgrep(&[&["--json"], flags, &["foo", a]].concat(), None);
+        stdout(&output)
+            .iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|record| record.get("type").is_none())
+            .collect()
+    };
+    assert_eq!(
+        vec![serde_json::json!({"path": a, "count": 2})],
+        records(&["-c"])
+    );
+    assert_eq!(
+        vec![serde_json::json!({"path": a, "count": 3})],
+        records(&["--count-matches"])
+    );
+    assert_eq!(vec![serde_json::json!({ "path": a })], records(&["-l"]));
+    for flags in [&["--count-percent"][..], &["-c", "-o"]] {
+        let output = tgrep(&[&["--json"], flags, &["foo", a]].concat(), None);
+        assert!(!output.status.success());
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn utf16() {
+    let root = tree("utf16", &[]);
+    fs::create_dir_all(&root).unwrap();
+    let mut content = vec![0xff, 0xfe];
+    for unit in "foo\r\nĊbar\r\nbaz foo\r\n".encode_utf16() {
+        content.extend(unit.to_le_bytes());
+    }
+    let path = root.join("utf16le.txt");
+    fs::write(&path, content).unwrap();
+    let output = tgrep(&["-o", "foo|Ċ", path.to_str().unwrap()], None);
+    assert_eq!(vec!["1: foo", "2: Ċ", "3: foo"], stdout(&output));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn match_only_prefix() {
+    let root = tree(
+        "match_only_prefix",
+        &[("a", "foo bar foo\n"), ("b", "x\nbar\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["-o", "foo|bar", root_str], None);
+    let mut lines = stdout(&output);
+    lines.sort();
+    // Every match names its source
+    assert_eq!(
+        vec![
+            format!("{}/a:1: bar", root_str),
+            format!("{}/a:1: foo", root_str),
+            format!("{}/a:1: foo", root_str),
+            format!("{}/b:2: bar", root_str),
+        ],
+        lines
+    );
+    let output = tgrep(&["-o", "--no-prefix-on-matches", "foo|bar", root_str], None);
+    let mut lines = stdout(&output);
+    lines.sort();
+    assert_eq!(vec!["bar", "bar", "foo", "foo"], lines);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn path_regex() {
+    let root = tree(
+        "path_regex",
+        &[
+            ("src/a.rs", "foo\n"),
+            ("src/a_test.rs", "foo\n"),
+            ("tests/cli.rs", "foo\n"),
+            ("tests/cli.txt", "foo\n"),
+            ("tests/bar.rs", "bar\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(
+        &["-l", "--path-regex", r"test.*\.rs$", "foo", root_str],
+        None,
+    );
+    let mut lines = stdout(&output);
+    lines.sort();
+    assert_eq!(
+        vec![
+            format!("{}/src/a_test.rs", root_str),
+            format!("{}/tests/cli.rs", root_str),
+        ],
+        lines
+    );
+    // Composes with the file filters
+    let output = tgrep(
+        &["-l", "--path-regex", "test", "-f", "src/*", "foo", root_str],
+        None,
+    );
+    assert_eq!(vec![format!("{}/src/a_test.rs", root_str)], stdout(&output));
+    // The path is relative to the searched directory, its ancestors don't match
+    let output = tgrep(&["-l", "--path-regex", "^src/", "foo", root_str], None);
+    let mut lines = stdout(&output);
+    lines.sort();
+    assert_eq!(
+        vec![
+            format!("{}/src/a.rs", root_str),
+            format!("{}/src/a_test.rs", root_str),
+        ],
+        lines
+    );
+    let src = root.join("src");
+    let output = tgrep(
+        &[
+            "-l",
+            "--path-regex",
+            "path_regex",
+            "foo",
+            src.to_str().unwrap(),
+        ],
+        None,
+    );
+    assert!(stdout(&output).is_empty());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn no_negated_ignore() {
+    let root = tree(
+        "no_negated_ignore",
+        &[
+            (".git/HEAD", "\n"),
+            (".gitignore", "*.log\n!keep.log\n"),
+            ("a.log", "foo\n"),
+            ("keep.log", "foo\n"),
+            ("sub/keep.log", "foo\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["-l", "foo", root_str], None);
+    let mut files = stdout(&output);
+    files.sort();
+    assert_eq!(
+        vec![
+            format!("{}/keep.log", root_str),
+            format!("{}/sub/keep.log", root_str)
+        ],
+        files
+    );
+    let output = tgrep(&["-l", "--no-negated-ignore", "foo", root_str], None);
+    assert!(stdout(&output).is_empty());
+    // The `.gitignore` files of the parents too
+    let sub = root.join("sub");
+    let output = tgrep(
+        &["-l", "--no-negated-ignore", "foo", sub.to_str().unwrap()],
+        None,
+    );
+    assert!(stdout(&output).is_empty());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_filters() {
+    let root = tree(
+        "symlink_filters",
+        &[
+            ("dir/a.rs", "foo\n"),
+            ("other/b.txt", "foo\n"),
+            ("other/c.rs", "foo\n"),
+        ],
+    );
+    let dir = root.join("dir");
+    std::os::unix::fs::symlink(root.join("other/b.txt"), dir.join("b.rs")).unwrap();
+    std::os::unix::fs::symlink(root.join("other/c.rs"), dir.join("c")).unwrap();
+    let output = tgrep(&["-l", "-t", "rs", "foo", dir.to_str().unwrap()], None);
+    let mut files = stdout(&output);
+    files.sort();
+    // The target is filtered rather than the link
+    assert_eq!(
+        vec![
+            format!("{}/a.rs", dir.to_str().unwrap()),
+            format!("{}/other/c.rs", root.to_str().unwrap()),
+        ],
+        files
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn glob_pattern() {
+    let input = "foo TODO bar\nTODO\ntodo later\nfoo/bar.rs\n";
+    let output = tgrep(&["--glob-pattern", "*TODO*"], Some(input));
+    assert_eq!(vec!["1: foo TODO bar", "2: TODO"], stdout(&output));
+    // The whole line must match
+    let output = tgrep(&["--glob-pattern", "TODO"], Some(input));
+    assert_eq!(vec!["2: TODO"], stdout(&output));
+    let output = tgrep(&["--glob-pattern", "-i", "todo*"], Some(input));
+    assert_eq!(vec!["2: TODO", "3: todo later"], stdout(&output));
+    // `*` crosses slashes
+    let output = tgrep(&["--glob-pattern", "foo*.rs"], Some(input));
+    assert_eq!(vec!["4: foo/bar.rs"], stdout(&output));
+    let output = tgrep(&["--glob-pattern", "-v", "*o*"], Some(input));
+    assert_eq!(vec!["2: TODO"], stdout(&output));
+    let output = tgrep(&["--glob-pattern", "-w", "foo"], Some(input));
+    assert!(!output.status.success());
+}
+
+#[test]
+fn ignore_case_filters() {
+    let root = tree(
+        "ignore_case_filters",
+        &[
+            ("Foo.RS", "foo\n"),
+            ("bar.rs", "FOO\n"),
+            ("baz.txt", "foo\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["-l", "-t", "rs", "foo", root_str], None);
+    assert!(stdout(&output).is_empty());
+    let output = tgrep(&["-l", "-i", "-t", "rs", "foo", root_str], None);
+    let mut files = stdout(&output);
+    files.sort();
+    assert_eq!(
+        vec![
+            format!("{}/Foo.RS", root_str),
+            format!("{}/bar.rs", root_str)
+        ],
+        files
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn summary_only() {
+    let root = tree(
+        "summary_only",
+        &[("a", "foo\nfoo\n"), ("b", "bar\n"), ("c", "foo bar foo\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["--summary-only", "foo", root_str], None);
+    assert_eq!(
+        vec!["2 files matched, 3 searched, 3 matching lines"],
+        stdout(&output)
+    );
+    assert!(output.status.success());
+    let output = tgrep(&["--summary-only", "foo"], Some("foo\n"));
+    assert_eq!(
+        vec!["1 files matched, 1 searched, 1 matching lines"],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn changed_within() {
+    let root = tree(
+        "changed_within",
+        &[("new", "foo\n"), ("old", "foo\n"), ("older", "foo\n")],
+    );
+    let now = SystemTime::now();
+    for (name, age) in [
+        ("new", 60),
+        ("old", 2 * 24 * 3600),
+        ("older", 30 * 24 * 3600),
+    ] {
+        let file = fs::File::options()
+            .write(true)
+            .open(root.join(name))
+            .unwrap();
+        if file.set_modified(now - Duration::from_secs(age)).is_err() {
+            // Not supported by the filesystem
+            fs::remove_dir_all(&root).unwrap();
+            return;
+        }
+    }
+    let root_str = root.to_str().unwrap();
+    let files = |args: &[&str]| -> Vec<String> {
+        let mut args = args.to_vec();
+        args.extend(["-l", "foo", root_str]);
+        let output = tgrep(&args, None);
+        let mut files: Vec<_> = stdout(&output)
+            .iter()
+            .map(|path| path.strip_prefix(root_str).unwrap().to_owned())
+            .collect();
+        files.sort();
+        files
+    };
+    assert_eq!(vec!["/new"], files(&["--changed-within", "1h"]));
+    assert_eq!(vec!["/new", "/old"], files(&["--changed-within", "1w"]));
+    assert_eq!(vec!["/old", "/older"], files(&["--changed-before", "1d"]));
+    assert_eq!(
+        vec!["/old"],
+        files(&["--changed-within", "7d", "--changed-before", "24h"])
+    );
+    assert!(!tgrep(&["--changed-within", "7", "foo", root_str], None)
+        .status
+        .success());
+    // Explicit files are searched regardless
+    let older = root.join("older");
+    let output = tgrep(
+        &["--changed-within", "1h", "foo", older.to_str().unwrap()],
+        None,
+    );
+    assert_eq!(vec!["1: foo"], stdout(&output));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn response_file() {
+    let root = tree(
+        "response_file",
+        &[("a", "foo\n"), ("b", "foo\n"), ("c", "foo\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let paths = root.join("paths");
+    fs::write(&paths, format!("{0}/b\n\n{0}/c\r\n", root_str)).unwrap();
+    let args = root.join("args");
+    fs::write(
+        &args,
+        format!("-l\nfoo\n{}/a\n@{}\n", root_str, paths.to_str().unwrap()),
+    )
+    .unwrap();
+    let output = tgrep(&[&format!("@{}", args.to_str().unwrap())], None);
+    let mut files = stdout(&output);
+    files.sort();
+    assert_eq!(
+        vec![
+            format!("{}/a", root_str),
+            format!("{}/b", root_str),
+            format!("{}/c", root_str),
+        ],
+        files
+    );
+    // Cycles are cut
+    fs::write(&paths, format!("@{}\n", args.to_str().unwrap())).unwrap();
+    let output = tgrep(&[&format!("@{}", args.to_str().unwrap())], None);
+    assert!(!output.status.success());
+    // Not a file, searched for as is
+    let output = tgrep(&["@nofile"], Some("@nofile\n"));
+    assert_eq!(vec!["1: @nofile"], stdout(&output));
+    // Escaped, or after `--`, even when naming a file
+    let args_str = args.to_str().unwrap();
+    let input = format!("@{}\n", args_str);
+    let escaped = format!("@@{}", args_str);
+    let output = tgrep(&[&escaped], Some(&input));
+    assert_eq!(vec![format!("1: @{}", args_str)], stdout(&output));
+    let output = tgrep(&["--", &format!("@{}", args_str)], Some(&input));
+    assert_eq!(vec![format!("1: @{}", args_str)], stdout(&output));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn max_line_length() {
+    let input = format!("{} foo\nfoo\n", "x".repeat(1 << 20));
+    let output = tgrep(&["-c", "foo"], Some(&input));
+    assert_eq!(vec!["2"], stdout(&output));
+    let output = tgrep(&["--max-line-length", "1000", "foo"], Some(&input));
+    assert_eq!(vec!["2: foo"], stdout(&output));
+    // Skipped lines are neither matching nor non-matching
+    let output = tgrep(&["--max-line-length", "1000", "-v", "bar"], Some(&input));
+    assert_eq!(vec!["2: foo"], stdout(&output));
+}
+
+#[test]
+fn filename_once() {
+    let root = tree(
+        "filename_once",
+        &[("a", "foo\nbar\nfoo\nbar\nfoo\n"), ("b", "foo\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(
+        &[
+            "--filename-once",
+            "--threads",
+            "1",
+            "--sort",
+            "count",
+            "foo",
+            root_str,
+        ],
+        None,
+    );
+    assert_eq!(
+        vec![
+            format!("{}/a:1: foo", root_str),
+            "3: foo".to_owned(),
+            "5: foo".to_owned(),
+            format!("{}/b:1: foo", root_str),
+        ],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = tree(
+        "permission_denied",
+        &[("a", "foo\n"), ("b", "foo\n"), ("c", "foo\n")],
+    );
+    let denied = root.join("b");
+    fs::set_permissions(&denied, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::read(&denied).is_ok() {
+        // Permissions are not enforced, e.g. for root
+        fs::remove_dir_all(&root).unwrap();
+        return;
+    }
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["-V", "foo", root_str], None);
+    let mut lines = stdout(&output);
+    lines.sort();
+    assert_eq!(
+        vec![
+            format!("{}/a:1: foo", root_str),
+            format!("{}/c:1: foo", root_str),
+        ],
+        lines
+    );
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(1, stderr.lines().count(), "{}", stderr);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn pretty() {
+    let root = tree("pretty", &[("a", "foo\nbar\nfoo\n")]);
+    let root_str = root.to_str().unwrap();
+    // `tgrep` passes --no-colour, which takes precedence
+    let output = tgrep(&["-p", "foo", root_str], None);
+    assert_eq!(
+        vec![format!("{}/a:1: foo", root_str), "3: foo".to_owned()],
+        stdout(&output)
+    );
+    let output = tgrep(&["-p", "--no-lno", "foo", root_str], None);
+    assert_eq!(
+        vec![format!("{}/a: foo", root_str), "foo".to_owned()],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn search_archive() {
+    let root = tree(
+        "search-archive",
+        &[("src/a.txt", "foo\nbar\n"), ("src/dir/b.txt", "foo\n")],
+    );
+    let root_str = root.to_str().unwrap();
+    let status = Command::new("tar")
+        .args(["-czf", "x.tgz", "-C", "src", "a.txt", "dir/b.txt"])
+        .current_dir(&root)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::remove_dir_all(root.join("src")).unwrap();
+    let output = tgrep(&["--search-archive", "foo", root_str], None);
+    let mut lines = stdout(&output);
+    lines.sort();
+    assert_eq!(
+        vec![
+            format!("{}/x.tgz:a.txt:1: foo", root_str),
+            format!("{}/x.tgz:dir/b.txt:1: foo", root_str),
+        ],
+        lines
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn zero_based_lines() {
+    let root = tree("zero-based-lines", &[("a", "foo\nbar\nbaz\n")]);
+    let path = root.join("a");
+    let path_str = path.to_str().unwrap();
+    let output = tgrep(&["--zero-based-lines", "foo", path_str], None);
+    assert_eq!(vec!["0: foo"], stdout(&output));
+    let output = tgrep(&["--zero-based-lines", "-B1", "baz", path_str], None);
+    assert_eq!(vec!["1- bar", "2: baz"], stdout(&output));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn exclude_from() {
+    let root = tree(
+        "exclude-from",
+        &[
+            ("a", "foo\n"),
+            ("Cargo.lock", "foo\n"),
+            ("sub/yarn.lock", "foo\n"),
+            ("excludes", "# lock files\n\n*.lock\nexcludes\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let excludes = root.join("excludes");
+    let output = tgrep(
+        &[
+            "--exclude-from",
+            excludes.to_str().unwrap(),
+            "foo",
+            root_str,
+        ],
+        None,
+    );
+    assert_eq!(vec![format!("{}/a:1: foo", root_str)], stdout(&output));
+    let output = tgrep(&["--exclude-from", "/nonexistent", "foo", root_str], None);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn ignore_file_root() {
+    let root = tree(
+        "ignore-file-root",
+        &[
+            ("sub/build/a", "foo\n"),
+            ("sub/sub/build/a", "foo\n"),
+            ("excludes", "/sub/build/\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let excludes = root.join("excludes");
+    let sub = root.join("sub");
+    let args = |with_root| {
+        let mut args = vec!["-l", "--exclude-from", excludes.to_str().unwrap()];
+        if with_root {
+            args.extend(["--ignore-file-root", root_str]);
+        }
+        args.extend(["foo", sub.to_str().unwrap()]);
+        args
+    };
+    // Relative to the searched path by default
+    let output = tgrep(&args(false), None);
+    assert_eq!(vec![format!("{}/sub/build/a", root_str)], stdout(&output));
+    let output = tgrep(&args(true), None);
+    assert_eq!(
+        vec![format!("{}/sub/sub/build/a", root_str)],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn match_only_context() {
+    let output = tgrep(
+        &["-o", "-A", "1", "-B", "1", "foo"],
+        Some("a foo b foo\nx\ny\nz\nfoo q\nr\n"),
+    );
+    // Overlapping windows are merged, disjoint ones are separated
+    assert_eq!(
+        vec!["1: foo", "1: foo", "2- x", "..", "4- z", "5: foo", "6- r"],
+        stdout(&output)
+    );
+}
+
+#[test]
+fn files_zero_match() {
+    let root = tree(
+        "files-zero-match",
+        &[
+            ("empty", ""),
+            ("all", "foo\nfoo\n"),
+            ("partial", "foo\nbar\n"),
+            ("none", "bar\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let files = |args: &[&str]| {
+        let mut args = args.to_vec();
+        args.extend(["foo", root_str]);
+        let output = tgrep(&args, None);
+        let mut files: Vec<_> = stdout(&output)
+            .iter()
+            .map(|path| path.strip_prefix(root_str).unwrap().to_owned())
+            .collect();
+        files.sort();
+        files
+    };
+    assert_eq!(vec!["/empty", "/none"], files(&["-L"]));
+    assert_eq!(vec!["/empty", "/none"], files(&["--files-zero-match"]));
+    // No line fails to match
+    assert_eq!(vec!["/all", "/empty"], files(&["--files-zero-match", "-v"]));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn count_matches() {
+    let input = Some("foo foo\nbar\nfoo\n");
+    // Two matches on the first line
+    let output = tgrep(&["-c", "foo"], input);
+    assert_eq!(vec!["2"], stdout(&output));
+    let output = tgrep(&["--count-lines", "foo"], input);
+    assert_eq!(vec!["2"], stdout(&output));
+    let output = tgrep(&["--count-matches", "foo"], input);
+    assert_eq!(vec!["3"], stdout(&output));
+    let output = tgrep(&["--count-matches", "-l", "foo"], input);
+    assert!(!output.status.success());
+    // Lines selected by -v have no matches to count
+    let output = tgrep(&["--count-matches", "-v", "foo"], input);
+    assert!(!output.status.success());
+
+    let root = tree("count_matches", &[("a", "foo foo\n"), ("b", "bar\n")]);
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(
+        &[
+            "--count-matches",
+            "--include-zero",
+            "--sort",
+            "count",
+            "foo",
+            root_str,
+        ],
+        None,
+    );
+    assert_eq!(
+        vec![format!("{}/a: 2", root_str), format!("{}/b: 0", root_str)],
+        stdout(&output)
+    );
+    // Without any count there is nothing to report zero for
+    let output = tgrep(&["--include-zero", "foo", root_str], None);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn invert_not_highlighted() {
+    let output = |args: &[&str]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_tgrep"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"foo\nbar\n")
+            .unwrap();
+        String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap()
+    };
+    let red = "\x1b[31m";
+    assert!(output(&["foo"]).contains(red));
+    let inverted = output(&["-v", "foo"]);
+    assert!(inverted.contains("bar"));
+    assert!(!inverted.contains(red));
+}
+
+#[test]
+fn path_separator() {
+    let root = tree("path-separator", &[("a/b/c", "foo\n")]);
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["--path-separator", ":", "-l", "foo", root_str], None);
+    assert_eq!(
+        vec![format!("{}/a/b/c", root_str).replace('/', ":")],
+        stdout(&output)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn git_tracked() {
+    let root = tree("git_tracked", &[("a", "foo\n"), ("sub/b", "foo\n")]);
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&root)
+            .args(["-c", "user.name=tgrep", "-c", "user.email=tgrep@localhost"])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "init"]);
+    // Staged files are tracked
+    fs::write(root.join("staged"), "foo\n").unwrap();
+    git(&["add", "staged"]);
+    fs::write(root.join("untracked"), "foo\n").unwrap();
+
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["-l", "foo", root_str], None);
+    assert_eq!(4, stdout(&output).len());
+    let output = tgrep(&["--git-tracked", "-l", "foo", root_str], None);
+    let mut lines = stdout(&output);
+    lines.sort();
+    assert_eq!(
+        vec![
+            format!("{}/a", root_str),
+            format!("{}/staged", root_str),
+            format!("{}/sub/b", root_str),
+        ],
+        lines
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn count_percent() {
+    let input = Some("foo\nbar\nbaz\nfoo\n");
+    let output = tgrep(&["--count-percent", "foo"], input);
+    assert_eq!(vec!["50%"], stdout(&output));
+    let output = tgrep(&["-c", "foo"], input);
+    assert_eq!(vec!["2"], stdout(&output));
+    // -c is still a flag that can be combined with others
+    let output = tgrep(&["-ci", "FOO"], input);
+    assert_eq!(vec!["2"], stdout(&output));
+    let output = tgrep(&["--count-percent", "-o", "foo"], input);
+    assert!(!output.status.success());
+    let output = tgrep(&["--count-percent", "-c", "foo"], input);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn word_count() {
+    let root = tree(
+        "word-count",
+        &[("a", "foo bar\nbaz\n"), ("b", "héllo  world\n"), ("c", "")],
+    );
+    let root_str = root.to_str().unwrap();
+    let output = tgrep(&["--word-count", "--sort", "count", root_str], None);
+    assert_eq!(
+        vec![
+            format!("{}/a: 3", root_str),
+            format!("{}/b: 2", root_str),
+            format!("{}/c: 0", root_str),
+            "5 total".to_owned(),
+        ],
+        stdout(&output)
+    );
+    let b = root.join("b");
+    let output = tgrep(&["--char-count", b.to_str().unwrap()], None);
+    assert_eq!(vec!["12", "12 total"], stdout(&output));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn heading_path() {
+    let root = tree("heading-path", &[("sub/dir/a", "foo\nfoo\n")]);
+    let root_str = root.to_str().unwrap();
+    // Full paths are canonicalized, relative ones keep the link
+    let search = format!("{}/link", root_str);
+    std::os::unix::fs::symlink(root.join("sub"), &search).unwrap();
+    let heading = |heading_path: &str| {
+        let output = tgrep(
+            &["-p", "--heading-path", heading_path, "foo", search.as_str()],
+            None,
+        );
+        stdout(&output)
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        vec![
+            format!("{}/sub/dir/a:1: foo", root_str),
+            "2: foo".to_owned()
+        ],
+        heading("full")
+    );
+    assert_eq!(
+        vec![format!("{}/dir/a:1: foo", search), "2: foo".to_owned()],
+        heading("relative")
+    );
+    assert_eq!(vec!["a:1: foo", "2: foo"], heading("basename"));
+    let output = tgrep(&["--heading-path", "full", "foo", root_str], None);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn timeout_per_file() {
+    let lines = 1 << 16;
+    let root = tree("timeout-per-file", &[("a", &"foo\n".repeat(lines))]);
+    let path = root.join("a");
+    let path_str = path.to_str().unwrap();
+    let output = tgrep(
+        &["-V", "--timeout-per-file", "0", "-c", "foo", path_str],
+        None,
+    );
+    let count: usize = stdout(&output)[0].parse().unwrap();
+    assert!(count < lines, "{}", count);
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains("Timed out searching"), "{}", stderr);
+    let output = tgrep(
+        &["--timeout-per-file", "60000", "-c", "foo", path_str],
+        None,
+    );
+    assert_eq!(vec![lines.to_string()], stdout(&output));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn no_unicode() {
+    let root = tree("no-unicode", &[("a", "héllo\n")]);
+    let path = root.join("a");
+    let path_str = path.to_str().unwrap();
+    let output = tgrep(&["-o", "--no-lno", r"\w+", path_str], None);
+    assert_eq!(vec!["héllo"], stdout(&output));
+    let output = tgrep(&["-o", "--no-lno", "--no-unicode", r"\w+", path_str], None);
+    assert_eq!(vec!["h", "llo"], stdout(&output));
+    let output = tgrep(&["--no-unicode", "h.llo", path_str], None);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn filename_match_type() {
+    let root = tree(
+        "filename-match-type",
+        &[("link-target/a", ""), ("other/link-file", "")],
+    );
+    let root_str = root.to_str().unwrap();
+    std::os::unix::fs::symlink(root.join("other/link-file"), root.join("link.txt")).unwrap();
+    let matches = |entry_type: &str| {
+        let output = tgrep(
+            &[
+                "--filename-only",
+                "--filename-match-type",
+                entry_type,
+                "^link",
+                root_str,
+            ],
+            None,
+        );
+        let mut lines: Vec<_> = stdout(&output).into_iter().map(str::to_owned).collect();
+        lines.sort();
+        lines
+    };
+    let path = |path: &str| format!("{}/{}", root_str, path);
+    assert_eq!(vec![path("other/link-file")], matches("file"));
+    assert_eq!(vec![path("link-target")], matches("dir"));
+    assert_eq!(vec![path("link.txt")], matches("symlink"));
+    assert_eq!(
+        vec![
+            path("link-target"),
+            path("link.txt"),
+            path("other/link-file")
+        ],
+        matches("any")
+    );
+    let output = tgrep(&["--filename-match-type", "any", "^link", root_str], None);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn repo_relative() {
+    let root = tree(
+        "repo-relative",
+        &[
+            ("repo/.git/HEAD", ""),
+            ("repo/sub/dir/a", "foo\n"),
+            ("plain/b", "foo\n"),
+        ],
+    );
+    let root_str = root.to_str().unwrap();
+    let search = root.join("repo/sub");
+    let output = tgrep(&["--repo-relative", "foo", search.to_str().unwrap()], None);
+    assert_eq!(vec!["sub/dir/a:1: foo"], stdout(&output));
+    // Outside of a repository paths are printed as without the flag
+    let plain = format!("{}/plain", root_str);
+    let output = tgrep(&["--repo-relative", "foo", &plain], None);
+    assert_eq!(vec![format!("{}/b:1: foo", plain)], stdout(&output));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn encoding_fallback() {
+    let root = tree("encoding-fallback", &[]);
+    fs::create_dir_all(&root).unwrap();
+    let path = root.join("a");
+    fs::write(&path, b"caf\xc3\xa9 utf-8\ncaf\xe9 latin-1\n").unwrap();
+    let path_str = path.to_str().unwrap();
+    let output = tgrep(
+        &["--encoding-fallback", "latin1", "-o", "café \\S+", path_str],
+        None,
+    );
+    assert_eq!(vec!["1: café utf-8", "2: café latin-1"], stdout(&output));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn fields() {
+    let root = tree("fields", &[("a", "x\n  foo bar foo\n")]);
+    let path = root.join("a");
+    let path_str = path.to_str().unwrap();
+    let fields = |fields: &str| {
+        let output = tgrep(&["--field", fields, "foo", path_str], None);
+        stdout(&output)
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![format!("{}:2", path_str)], fields("path,lno"));
+    assert_eq!(vec!["  foo bar foo"], fields("line"));
+    assert_eq!(vec!["2:3"], fields("lno,col"));
+    assert_eq!(vec!["3:foo", "11:foo"], fields("col,match"));
+    assert_eq!(
+        vec!["1:3"],
+        stdout(&tgrep(
+            &["--zero-based-lines", "--field", "lno,col", "foo", path_str],
+            None
+        ))
+    );
+    let output = tgrep(&["--field", "lno,nope", "foo", path_str], None);
+    assert!(!output.status.success());
+    let output = tgrep(&["-v", "--field", "lno,match", "foo", path_str], None);
+    assert!(!output.status.success());
+    let output = tgrep(&["--json", "--zero-based-lines", "foo", path_str], None);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn offsets() {
+    let root = tree("offsets", &[("a", "x\n\t\tfoo bar\n")]);
+    let path = root.join("a");
+    let path_str = path.to_str().unwrap();
+    let fields = |args: &[&str]| {
+        let args = [args, &["--field", "col,match,line", "foo", path_str]].concat();
+        stdout(&tgrep(&args, None))
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["3:foo:\t\tfoo bar"], fields(&[]));
+    // The column refers to the line as read unless the displayed one is asked for
+    assert_eq!(vec!["3:foo:→→foo bar"], fields(&["--show-whitespace"]));
+    assert_eq!(
+        vec!["7:foo:→→foo bar"],
+        fields(&["--show-whitespace", "--offsets", "displayed"])
+    );
+    assert_eq!(
+        vec!["3:foo:\t\tfoo bar"],
+        fields(&["--offsets", "displayed"])
+    );
+    let output = tgrep(&["--offsets", "nope", "foo", path_str], None);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&root).unwrap();
+}